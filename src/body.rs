@@ -0,0 +1,268 @@
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    time::Instant,
+};
+
+use flate2::{Compression, write::GzEncoder};
+use reqwest::blocking::Response;
+use sha2::{Digest, Sha256};
+
+use crate::digest::hex;
+
+/// Why a response body couldn't be read.
+pub enum ReadError {
+    /// The overall deadline for the request passed while the body was still arriving
+    Deadline,
+    /// No data arrived within the client's per-read timeout
+    TimedOut,
+    /// The body is larger than the limit
+    TooLarge(usize),
+    /// Reading the body failed
+    Io(io::Error),
+    /// Writing the body out failed
+    Write(io::Error),
+}
+
+/// Where the body of the final response to a request goes.
+pub enum BodyTarget {
+    /// Read into memory
+    Memory,
+    /// Streamed to a file without being held in memory
+    File {
+        file: File,
+        path: String,
+        /// The number of bytes written so far
+        written: u64,
+        /// The start of the body, for describing a failed status
+        excerpt: Vec<u8>,
+    },
+    /// Encoded as base64
+    Base64 {
+        encoded: String,
+        /// The number of bytes encoded so far
+        size: u64,
+        /// The start of the body, for describing a failed status
+        excerpt: Vec<u8>,
+    },
+}
+
+/// Read a response body in chunks, giving up if `deadline` passes before it has all arrived or if
+/// it grows beyond `limit` bytes.
+pub fn read(
+    res: &mut Response,
+    deadline: Option<Instant>,
+    limit: Option<usize>,
+) -> Result<Vec<u8>, ReadError> {
+    let mut body = Vec::new();
+    copy(res, &mut body, deadline, limit)?;
+    Ok(body)
+}
+
+/// Copy a response body to `writer` in chunks, with the same limits as [`read`], returning the
+/// number of bytes copied.
+pub fn copy<W: Write>(
+    res: &mut Response,
+    writer: &mut W,
+    deadline: Option<Instant>,
+    limit: Option<usize>,
+) -> Result<u64, ReadError> {
+    if let Some(limit) = limit
+        && res.content_length().is_some_and(|len| len > limit as u64)
+    {
+        return Err(ReadError::TooLarge(limit));
+    }
+    let mut copied = 0;
+    let mut buf = [0; 16 * 1024];
+    loop {
+        if deadline.is_some_and(|deadline| Instant::now() > deadline) {
+            return Err(ReadError::Deadline);
+        }
+        match res.read(&mut buf) {
+            Ok(0) => return Ok(copied as u64),
+            Ok(n) => {
+                if let Some(limit) = limit
+                    && copied + n > limit
+                {
+                    return Err(ReadError::TooLarge(limit));
+                }
+                writer.write_all(&buf[..n]).map_err(ReadError::Write)?;
+                copied += n;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) if is_timeout(&e) => return Err(ReadError::TimedOut),
+            Err(e) => return Err(ReadError::Io(e)),
+        }
+    }
+}
+
+/// A writer that hashes everything written through it.
+pub struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// The lowercase hex SHA-256 of everything written.
+    pub fn sha256(self) -> String {
+        hex(&self.hasher.finalize())
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// How much of a body that isn't kept in memory is kept to describe a failed status.
+const EXCERPT_LEN: usize = 1024;
+
+/// A writer that keeps the first bytes written through it in `excerpt`.
+pub struct ExcerptWriter<'a, W> {
+    inner: W,
+    excerpt: &'a mut Vec<u8>,
+}
+
+impl<'a, W: Write> ExcerptWriter<'a, W> {
+    pub fn new(inner: W, excerpt: &'a mut Vec<u8>) -> Self {
+        Self { inner, excerpt }
+    }
+}
+
+impl<W: Write> Write for ExcerptWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        let wanted = EXCERPT_LEN.saturating_sub(self.excerpt.len()).min(n);
+        self.excerpt.extend_from_slice(&buf[..wanted]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Compress data with gzip.
+pub fn gzip(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+/// Whether a read failed because the client's timeout passed. The blocking client may report this
+/// as an I/O error wrapping a reqwest timeout error.
+fn is_timeout(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::TimedOut
+        || err
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<reqwest::Error>())
+            .is_some_and(reqwest::Error::is_timeout)
+}
+
+/// Format a size in bytes for people, e.g. "10 MiB".
+pub fn format_size(bytes: usize) -> String {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB"];
+    if bytes < 1024 {
+        return format!("{bytes} bytes");
+    }
+    let mut size = bytes as f64;
+    let mut unit = "bytes";
+    for u in UNITS {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = u;
+    }
+    if size.fract() == 0.0 {
+        format!("{size} {unit}")
+    } else {
+        format!("{size:.1} {unit}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use super::*;
+    use crate::{
+        client_config::ClientConfig,
+        test_server::{self, TestServer},
+    };
+
+    /// Start a server that sends a 20 byte body one byte every 50ms.
+    fn trickling_server() -> TestServer {
+        TestServer::start(|stream| {
+            let mut reader = io::BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            test_server::read_request(&mut reader);
+            let _ = writer.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 20\r\n\r\n");
+            for _ in 0..20 {
+                thread::sleep(Duration::from_millis(50));
+                if writer.write_all(b".").is_err() {
+                    break;
+                }
+            }
+        })
+    }
+
+    fn read_trickle(timeout: u64, read_timeout: Option<u64>) -> Result<Vec<u8>, ReadError> {
+        let server = trickling_server();
+        let config = ClientConfig {
+            timeout: Some(Duration::from_millis(timeout)),
+            read_timeout: read_timeout.map(Duration::from_millis),
+            ..Default::default()
+        };
+        let started = Instant::now();
+        let mut res = config.build().unwrap().get(server.url("/")).send().unwrap();
+        read(&mut res, config.body_deadline(started), None)
+    }
+
+    #[test]
+    fn slow_but_steady_bodies_pass_the_read_timeout() {
+        let Ok(body) = read_trickle(5000, Some(300)) else {
+            panic!("the body should have been read");
+        };
+        assert_eq!(body.len(), 20);
+    }
+
+    #[test]
+    fn slow_but_steady_bodies_are_cut_off_by_the_total_timeout() {
+        assert!(matches!(
+            read_trickle(400, Some(300)),
+            Err(ReadError::Deadline)
+        ));
+    }
+
+    #[test]
+    fn the_total_timeout_applies_without_a_read_timeout() {
+        assert!(matches!(read_trickle(400, None), Err(ReadError::Deadline)));
+    }
+
+    #[test]
+    fn excerpts_keep_only_the_start_of_the_body() {
+        let mut excerpt = vec![];
+        let mut written = vec![];
+        let mut writer = ExcerptWriter::new(&mut written, &mut excerpt);
+        writer.write_all(&[b'a'; 1000]).unwrap();
+        writer.write_all(&[b'b'; 1000]).unwrap();
+        assert_eq!(written.len(), 2000);
+        assert_eq!(excerpt.len(), EXCERPT_LEN);
+        assert_eq!(excerpt[EXCERPT_LEN - 1], b'b');
+    }
+}
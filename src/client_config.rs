@@ -0,0 +1,597 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+use reqwest::{
+    Certificate, Identity, NoProxy, Proxy, Url,
+    blocking::Client,
+    header::{self, HeaderMap, HeaderValue},
+    redirect,
+};
+use reqwest_cookie_store::CookieStoreMutex;
+use rustls::pki_types::CertificateDer;
+
+use crate::{
+    dns::{FamilyResolver, IpFamily},
+    error::Result,
+    timings::{ConnectTimes, ConnectTimingLayer},
+    tls,
+};
+
+/// The total timeout applied to requests unless one is configured.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+/// The time allowed to establish a connection unless one is configured.
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How redirects are handled.
+#[derive(Clone, Copy)]
+pub enum Redirects {
+    /// Follow up to this many redirects, failing if there are more
+    Follow(usize),
+    /// Don't follow redirects, returning the 3xx response itself
+    None,
+}
+
+/// Which HTTP versions the client uses.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum HttpProtocol {
+    /// HTTP/2 where the server offers it through ALPN, otherwise HTTP/1.1
+    Auto,
+    /// Only HTTP/1.1, never offering HTTP/2
+    Http1Only,
+    /// HTTP/2 without negotiation, including h2c over plain connections
+    Http2PriorKnowledge,
+    /// HTTP/3 over QUIC
+    #[cfg(feature = "http3")]
+    Http3,
+}
+
+impl HttpProtocol {
+    /// Parse a protocol from the names accepted by `SetHttpProtocol`.
+    pub fn parse(mode: &str) -> Option<Self> {
+        match mode {
+            "auto" => Some(Self::Auto),
+            "http1-only" => Some(Self::Http1Only),
+            "http2-prior-knowledge" => Some(Self::Http2PriorKnowledge),
+            #[cfg(feature = "http3")]
+            "http3" => Some(Self::Http3),
+            _ => None,
+        }
+    }
+
+    /// The name accepted by `SetHttpProtocol` for this protocol.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Auto => "auto",
+            Self::Http1Only => "http1-only",
+            Self::Http2PriorKnowledge => "http2-prior-knowledge",
+            #[cfg(feature = "http3")]
+            Self::Http3 => "http3",
+        }
+    }
+}
+
+/// Client-level configuration. The client is rebuilt from this whenever an option changes, so
+/// every option is preserved across rebuilds.
+#[derive(Clone)]
+pub struct ClientConfig {
+    /// The proxy all requests are routed through, if any
+    pub proxy: Option<String>,
+    /// The basic auth credentials for the proxy, if any
+    pub proxy_auth: Option<(String, String)>,
+    /// Comma-separated hosts, domain suffixes and IP ranges that bypass the proxy
+    pub no_proxy: Option<String>,
+    /// Whether to ignore the HTTP_PROXY, HTTPS_PROXY and NO_PROXY environment variables when no
+    /// proxy is configured
+    pub ignore_system_proxy: bool,
+
+    /// Whether invalid TLS certificates are accepted
+    pub accept_invalid_certs: bool,
+    /// Whether TLS certificates are accepted for hostnames they weren't issued to
+    pub accept_invalid_hostnames: bool,
+    /// Extra DER root certificates to trust alongside the built-in ones
+    pub root_certificates: Vec<CertificateDer<'static>>,
+    /// The client certificate presented for mutual TLS, if any
+    pub identity: Option<Identity>,
+    /// The SPKI SHA-256 hashes the certificate for each host must match
+    pub pins: HashMap<String, Vec<String>>,
+    /// Where the reason the latest handshake failed a pin check is recorded, shared by every
+    /// client built from this configuration
+    pub pin_failure: Arc<Mutex<Option<String>>>,
+    /// Where the TLS version negotiated with each host is recorded when connections use rustls
+    pub negotiated_tls_versions: Arc<Mutex<HashMap<String, &'static str>>>,
+    /// The lowest TLS version allowed, as accepted by [`parse_tls_version`]
+    pub min_tls_version: Option<String>,
+    /// The highest TLS version allowed, as accepted by [`parse_tls_version`]
+    pub max_tls_version: Option<String>,
+
+    /// The addresses used instead of resolving each hostname. A port of 0 keeps the URL's port
+    pub resolve: HashMap<String, SocketAddr>,
+    /// The Unix socket every request connects to instead of its host, if any
+    pub unix_socket: Option<String>,
+    /// The local address connections are made from, if not chosen by the OS
+    pub local_address: Option<IpAddr>,
+    /// Which address families hostnames may resolve to
+    pub ip_family: IpFamily,
+    /// Where the resolver records how long its latest lookup took, shared by every client built
+    /// from this configuration
+    pub dns_lookup_time: Arc<Mutex<Option<Duration>>>,
+    /// Where the connector records how long the latest new connection took to set up, shared by
+    /// every client built from this configuration
+    pub connect_times: ConnectTimes,
+
+    /// The total time allowed for a request, if limited
+    pub timeout: Option<Duration>,
+    /// The time allowed to establish a connection, if limited
+    pub connect_timeout: Option<Duration>,
+    /// The time allowed between bytes of the response arriving, if limited
+    pub read_timeout: Option<Duration>,
+
+    /// How often TCP keepalive probes are sent, if not left at the default
+    pub tcp_keepalive: Option<Duration>,
+    /// Whether Nagle's algorithm is disabled, if not left at the default
+    pub tcp_nodelay: Option<bool>,
+
+    /// The most idle connections kept open to each host
+    pub pool_max_idle_per_host: usize,
+    /// How long idle connections are kept open, if limited
+    pub pool_idle_timeout: Option<Duration>,
+
+    /// How redirects are handled
+    pub redirects: Redirects,
+    /// Which HTTP versions are used
+    pub protocol: HttpProtocol,
+    /// Whether gzip, brotli and deflate responses are decompressed
+    pub auto_decompress: bool,
+    /// The Accept-Encoding header sent instead of the one matching `auto_decompress`, if set
+    pub accept_encoding: Option<String>,
+
+    /// The cookie jar that stores cookies from responses and sends them with requests, if enabled
+    pub cookie_jar: Option<Arc<CookieStoreMutex>>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            proxy: None,
+            proxy_auth: None,
+            no_proxy: None,
+            ignore_system_proxy: false,
+            accept_invalid_certs: false,
+            accept_invalid_hostnames: false,
+            root_certificates: vec![],
+            identity: None,
+            pins: HashMap::new(),
+            pin_failure: Arc::default(),
+            negotiated_tls_versions: Arc::default(),
+            min_tls_version: None,
+            max_tls_version: None,
+            resolve: HashMap::new(),
+            unix_socket: None,
+            local_address: None,
+            ip_family: IpFamily::Any,
+            dns_lookup_time: Arc::default(),
+            connect_times: ConnectTimes::default(),
+            timeout: Some(DEFAULT_TIMEOUT),
+            connect_timeout: Some(DEFAULT_CONNECT_TIMEOUT),
+            read_timeout: None,
+            tcp_keepalive: None,
+            tcp_nodelay: None,
+            pool_max_idle_per_host: usize::MAX,
+            pool_idle_timeout: Some(Duration::from_secs(90)),
+            redirects: Redirects::Follow(10),
+            protocol: HttpProtocol::Auto,
+            auto_decompress: true,
+            accept_encoding: None,
+            cookie_jar: None,
+        }
+    }
+}
+
+/// Parse a TLS version from "1.0", "1.1", "1.2" or "1.3".
+pub fn parse_tls_version(version: &str) -> Option<reqwest::tls::Version> {
+    match version {
+        "1.0" => Some(reqwest::tls::Version::TLS_1_0),
+        "1.1" => Some(reqwest::tls::Version::TLS_1_1),
+        "1.2" => Some(reqwest::tls::Version::TLS_1_2),
+        "1.3" => Some(reqwest::tls::Version::TLS_1_3),
+        _ => None,
+    }
+}
+
+impl ClientConfig {
+    /// Build a new client from this configuration.
+    pub fn build(&self) -> Result<Client> {
+        let mut builder = Client::builder();
+        if let Some(proxy) = &self.proxy {
+            let mut proxy = Proxy::all(proxy)?;
+            if let Some((username, password)) = &self.proxy_auth {
+                proxy = proxy.basic_auth(username, password);
+            }
+            if let Some(no_proxy) = &self.no_proxy {
+                proxy = proxy.no_proxy(NoProxy::from_string(no_proxy));
+            }
+            builder = builder.proxy(proxy);
+        } else if self.ignore_system_proxy {
+            builder = builder.no_proxy();
+        }
+        builder = builder
+            .danger_accept_invalid_certs(self.accept_invalid_certs)
+            .danger_accept_invalid_hostnames(self.accept_invalid_hostnames);
+        for cert in &self.root_certificates {
+            builder = builder.add_root_certificate(Certificate::from_der(cert)?);
+        }
+        if let Some(identity) = &self.identity {
+            builder = builder.identity(identity.clone());
+        }
+        for (host, addr) in &self.resolve {
+            builder = builder.resolve(host, *addr);
+        }
+        #[cfg(unix)]
+        if let Some(path) = &self.unix_socket {
+            builder = builder.unix_socket(std::path::PathBuf::from(path));
+        }
+        builder = builder.local_address(self.local_address);
+        // The engine's own resolver is always used, so lookups can be timed
+        builder = builder.dns_resolver(Arc::new(FamilyResolver {
+            family: self.ip_family,
+            lookup_time: self.dns_lookup_time.clone(),
+        }));
+        builder = builder.connector_layer(ConnectTimingLayer {
+            times: self.connect_times.clone(),
+            dns_lookup_time: self.dns_lookup_time.clone(),
+        });
+        // The blocking client applies its timeout to each wait for data rather than to the whole
+        // request, so the total timeout on reading the body is enforced by the engine instead.
+        // Waiting for the headers is limited by whichever timeout is shorter
+        let wait_timeout = match (self.read_timeout, self.timeout) {
+            (Some(read), Some(total)) => Some(read.min(total)),
+            (read, total) => read.or(total),
+        };
+        builder = builder
+            .timeout(wait_timeout)
+            .connect_timeout(self.connect_timeout);
+        if let Some(keepalive) = self.tcp_keepalive {
+            builder = builder.tcp_keepalive(keepalive);
+        }
+        if let Some(nodelay) = self.tcp_nodelay {
+            builder = builder.tcp_nodelay(nodelay);
+        }
+        builder = builder
+            .pool_max_idle_per_host(self.pool_max_idle_per_host)
+            .pool_idle_timeout(self.pool_idle_timeout);
+        // Redirects are followed by the engine, so every hop can be recorded
+        builder = builder.redirect(redirect::Policy::none());
+        builder = match self.protocol {
+            HttpProtocol::Auto => builder,
+            HttpProtocol::Http1Only => builder.http1_only(),
+            HttpProtocol::Http2PriorKnowledge => builder.http2_prior_knowledge(),
+            // QUIC connections are only supported with rustls
+            #[cfg(feature = "http3")]
+            HttpProtocol::Http3 => builder.use_rustls_tls().http3_prior_knowledge(),
+        };
+        builder = builder
+            .gzip(self.auto_decompress)
+            .brotli(self.auto_decompress)
+            .deflate(self.auto_decompress);
+        if let Some(accept_encoding) = &self.accept_encoding {
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                header::ACCEPT_ENCODING,
+                HeaderValue::from_str(accept_encoding)
+                    .expect("the Accept-Encoding header is validated when it's set"),
+            );
+            builder = builder.default_headers(headers);
+        }
+        if let Some(jar) = &self.cookie_jar {
+            builder = builder.cookie_provider(jar.clone());
+        }
+        if self.uses_rustls() {
+            if self.identity.is_some() {
+                return Err("Certificates can't be pinned while a client certificate is set, as pinned connections use rustls, which can't load it".into());
+            }
+            builder = builder.use_preconfigured_tls(tls::rustls_config(self)?);
+        } else {
+            if self.min_tls_version.as_deref() == Some("1.3") {
+                return Err("TLS 1.3 can't be required while a client certificate is set, as the platform TLS library can't enforce it".into());
+            }
+            if let Some(version) = self.min_tls_version.as_deref().and_then(parse_tls_version) {
+                builder = builder.min_tls_version(version);
+            }
+            // The platform TLS library can't be limited to TLS 1.3, but as the newest version
+            // there's nothing above it to exclude
+            if let Some(version) = self
+                .max_tls_version
+                .as_deref()
+                .filter(|v| *v != "1.3")
+                .and_then(parse_tls_version)
+            {
+                builder = builder.max_tls_version(version);
+            }
+        }
+        // The peer certificate is recorded in evidence
+        builder = builder.tls_info(true);
+        Ok(builder.build()?)
+    }
+
+    /// Whether connections use rustls rather than the platform TLS library. rustls checks pins
+    /// during the handshake and reports the negotiated TLS version, so it's used for pinned
+    /// certificates and for limits within TLS 1.2 and 1.3, unless a client certificate is set,
+    /// which it can't load.
+    pub fn uses_rustls(&self) -> bool {
+        let modern = |version: &Option<String>| version.as_deref().is_none_or(|v| v >= "1.2");
+        let limited = self.min_tls_version.is_some() || self.max_tls_version.is_some();
+        !self.pins.is_empty()
+            || (limited
+                && modern(&self.min_tls_version)
+                && modern(&self.max_tls_version)
+                && self.identity.is_none())
+    }
+
+    /// Whether any part of TLS certificate validation has been disabled.
+    pub fn tls_validation_disabled(&self) -> bool {
+        self.accept_invalid_certs || self.accept_invalid_hostnames
+    }
+
+    /// When a request started at `started` must have finished reading its body by, if the total
+    /// time is limited.
+    pub fn body_deadline(&self, started: Instant) -> Option<Instant> {
+        self.timeout.map(|timeout| started + timeout)
+    }
+
+    /// The proxy URL with any credentials removed, suitable for error messages.
+    pub fn proxy_display(&self) -> Option<String> {
+        let proxy = self.proxy.as_ref()?;
+        match Url::parse(proxy) {
+            Ok(mut url) => {
+                let _ = url.set_username("");
+                let _ = url.set_password(None);
+                Some(url.to_string())
+            }
+            Err(_) => Some(proxy.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{self, Read, Write},
+        net::{Ipv4Addr, Ipv6Addr, TcpStream},
+        sync::mpsc::{self, Sender},
+    };
+
+    use super::*;
+    use crate::test_server::{self, TestServer};
+
+    /// What a SOCKS5 client asked the proxy for.
+    struct SocksConnect {
+        /// The authentication methods the client offered
+        methods: Vec<u8>,
+        /// The address the client asked to connect to, as "host:port"
+        target: String,
+    }
+
+    /// Act as a SOCKS5 proxy that only supports connecting without authentication, reporting
+    /// what the client asked for and then answering HTTP requests itself as if it were the
+    /// target.
+    fn socks5(mut stream: TcpStream, connects: Sender<SocksConnect>) -> io::Result<()> {
+        let mut greeting = [0; 2];
+        stream.read_exact(&mut greeting)?;
+        let mut methods = vec![0; usize::from(greeting[1])];
+        stream.read_exact(&mut methods)?;
+        let no_auth = methods.contains(&0);
+        stream.write_all(&[5, if no_auth { 0 } else { 0xff }])?;
+        if !no_auth {
+            return Ok(());
+        }
+
+        let mut request = [0; 4];
+        stream.read_exact(&mut request)?;
+        let host = match request[3] {
+            1 => {
+                let mut ip = [0; 4];
+                stream.read_exact(&mut ip)?;
+                Ipv4Addr::from(ip).to_string()
+            }
+            3 => {
+                let mut len = [0];
+                stream.read_exact(&mut len)?;
+                let mut name = vec![0; usize::from(len[0])];
+                stream.read_exact(&mut name)?;
+                String::from_utf8_lossy(&name).into_owned()
+            }
+            _ => {
+                let mut ip = [0; 16];
+                stream.read_exact(&mut ip)?;
+                format!("[{}]", Ipv6Addr::from(ip))
+            }
+        };
+        let mut port = [0; 2];
+        stream.read_exact(&mut port)?;
+        let _ = connects.send(SocksConnect {
+            methods,
+            target: format!("{host}:{}", u16::from_be_bytes(port)),
+        });
+        stream.write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 0])?;
+        test_server::serve(stream, "via socks");
+        Ok(())
+    }
+
+    fn socks_proxy() -> (TestServer, mpsc::Receiver<SocksConnect>) {
+        let (sender, connects) = mpsc::channel();
+        let proxy = TestServer::start(move |stream| {
+            let _ = socks5(stream, sender.clone());
+        });
+        (proxy, connects)
+    }
+
+    #[test]
+    fn socks5h_resolves_the_host_on_the_proxy() {
+        let (proxy, connects) = socks_proxy();
+        let config = ClientConfig {
+            proxy: Some(format!("socks5h://{}", proxy.addr)),
+            ..Default::default()
+        };
+        // The .invalid domain never resolves, so only the proxy can connect to it
+        let body = config
+            .build()
+            .unwrap()
+            .get("http://only-the-proxy-knows.invalid/")
+            .send()
+            .unwrap()
+            .text()
+            .unwrap();
+        assert_eq!(body, "via socks");
+        assert_eq!(
+            connects.recv().unwrap().target,
+            "only-the-proxy-knows.invalid:80"
+        );
+    }
+
+    #[test]
+    fn socks5_resolves_the_host_locally() {
+        let (proxy, connects) = socks_proxy();
+        let config = ClientConfig {
+            proxy: Some(format!("socks5://{}", proxy.addr)),
+            ..Default::default()
+        };
+        let res = config.build().unwrap().get("http://localhost:8080/").send();
+        assert_eq!(res.unwrap().text().unwrap(), "via socks");
+        let target = connects.recv().unwrap().target;
+        assert!(target.ends_with(":8080"), "{target}");
+        assert!(!target.starts_with("localhost"), "{target}");
+    }
+
+    #[test]
+    fn socks_proxies_without_auth_are_supported() {
+        let (proxy, connects) = socks_proxy();
+        let config = ClientConfig {
+            proxy: Some(format!("socks5h://{}", proxy.addr)),
+            ..Default::default()
+        };
+        let res = config.build().unwrap().get("http://example.com/").send();
+        assert_eq!(res.unwrap().text().unwrap(), "via socks");
+        assert_eq!(connects.recv().unwrap().methods, vec![0]);
+    }
+
+    #[test]
+    fn a_longer_read_timeout_keeps_the_total_timeout_for_headers() {
+        // Accepts connections but never answers
+        let server = TestServer::start(|stream| {
+            std::thread::sleep(Duration::from_secs(5));
+            drop(stream);
+        });
+        let config = ClientConfig {
+            timeout: Some(Duration::from_millis(200)),
+            read_timeout: Some(Duration::from_secs(10)),
+            ..Default::default()
+        };
+        let started = Instant::now();
+        let err = config
+            .build()
+            .unwrap()
+            .get(server.url("/"))
+            .send()
+            .unwrap_err();
+        assert!(err.is_timeout(), "{err}");
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    /// Send two requests in a row with `config` to a new server, returning how many connections
+    /// the server accepted.
+    fn connections_for_two_requests(config: ClientConfig) -> usize {
+        let server = TestServer::responding("ok");
+        let client = config.build().unwrap();
+        for _ in 0..2 {
+            let res = client.get(server.url("/")).send().unwrap();
+            assert_eq!(res.text().unwrap(), "ok");
+            // Gives the connection time to go back to the pool
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        server.accepts()
+    }
+
+    #[test]
+    fn no_idle_connections_opens_one_per_request() {
+        let config = ClientConfig {
+            pool_max_idle_per_host: 0,
+            ..Default::default()
+        };
+        assert_eq!(connections_for_two_requests(config), 2);
+    }
+
+    #[test]
+    fn idle_connections_are_reused() {
+        assert_eq!(connections_for_two_requests(ClientConfig::default()), 1);
+    }
+
+    fn tls_versions(min: &str, max: &str) -> ClientConfig {
+        ClientConfig {
+            min_tls_version: (!min.is_empty()).then(|| min.to_string()),
+            max_tls_version: (!max.is_empty()).then(|| max.to_string()),
+            // Keeps the test independent of the platform's trusted roots
+            accept_invalid_certs: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn tls_1_2_and_1_3_limits_use_rustls() {
+        assert!(tls_versions("1.2", "1.3").uses_rustls());
+        assert!(tls_versions("1.3", "").uses_rustls());
+        assert!(!tls_versions("1.0", "1.3").uses_rustls());
+        assert!(!tls_versions("", "1.1").uses_rustls());
+        assert!(!tls_versions("", "").uses_rustls());
+    }
+
+    #[test]
+    fn tls_1_3_limits_build_with_either_library() {
+        tls_versions("1.2", "1.3").build().unwrap();
+        tls_versions("1.3", "1.3").build().unwrap();
+        tls_versions("1.0", "1.3").build().unwrap();
+    }
+
+    /// Send a request for `url` through a proxy that `no_proxy` may bypass, returning "proxied"
+    /// if it went through the proxy or "direct" if it didn't.
+    fn route(no_proxy: &str, url: &str) -> String {
+        let proxy = TestServer::responding("proxied");
+        let direct = TestServer::responding("direct");
+        let mut config = ClientConfig {
+            proxy: Some(proxy.url("")),
+            no_proxy: Some(no_proxy.to_string()),
+            ..Default::default()
+        };
+        for host in ["api.test", "svc.internal.test", "other.test"] {
+            config.resolve.insert(host.to_string(), direct.addr);
+        }
+        let url = url.replace("PORT", &direct.addr.port().to_string());
+        let res = config.build().unwrap().get(url).send().unwrap();
+        res.text().unwrap()
+    }
+
+    #[test]
+    fn no_proxy_matches_exact_hosts() {
+        assert_eq!(route("api.test", "http://api.test:PORT/"), "direct");
+        assert_eq!(route("api.test", "http://other.test:PORT/"), "proxied");
+    }
+
+    #[test]
+    fn no_proxy_matches_domain_suffixes() {
+        assert_eq!(
+            route(".internal.test", "http://svc.internal.test:PORT/"),
+            "direct"
+        );
+        assert_eq!(route(".internal.test", "http://api.test:PORT/"), "proxied");
+    }
+
+    #[test]
+    fn no_proxy_matches_ip_ranges() {
+        assert_eq!(route("127.0.0.0/8", "http://127.0.0.1:PORT/"), "direct");
+        assert_eq!(route("10.0.0.0/8", "http://127.0.0.1:PORT/"), "proxied");
+    }
+}
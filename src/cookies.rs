@@ -0,0 +1,100 @@
+use std::{
+    convert::Infallible,
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+};
+
+use cookie_store::{Cookie, CookieDomain, CookieStore, RawCookie};
+use reqwest::Url;
+
+use crate::error::Result;
+
+/// Whether a cookie in the jar applies to `domain`. An empty domain matches every cookie.
+pub fn matches_domain(cookie: &Cookie, domain: &str) -> bool {
+    let domain = domain.trim_start_matches('.').to_lowercase();
+    if domain.is_empty() {
+        return true;
+    }
+    match &cookie.domain {
+        CookieDomain::HostOnly(host) => host.eq_ignore_ascii_case(&domain),
+        CookieDomain::Suffix(suffix) => {
+            domain == suffix.to_lowercase()
+                || domain.ends_with(&format!(".{}", suffix.to_lowercase()))
+        }
+        _ => false,
+    }
+}
+
+/// Whether a cookie in the jar was set for exactly `domain`.
+fn is_for_domain(cookie: &Cookie, domain: &str) -> bool {
+    match &cookie.domain {
+        CookieDomain::HostOnly(d) | CookieDomain::Suffix(d) => {
+            d.eq_ignore_ascii_case(domain.trim_start_matches('.'))
+        }
+        _ => false,
+    }
+}
+
+/// Add a cookie to the jar as if `domain` had set it. A leading `.` on the domain makes it a
+/// domain cookie, sent to subdomains too, otherwise it is host-only.
+pub fn insert(
+    store: &mut CookieStore,
+    domain: &str,
+    name: &str,
+    value: &str,
+    path: &str,
+    secure: bool,
+    http_only: bool,
+) -> Result<()> {
+    let path = if path.is_empty() { "/" } else { path };
+    let host = domain.trim_start_matches('.');
+    let mut cookie = RawCookie::build((name.to_string(), value.to_string()))
+        .path(path.to_string())
+        .secure(secure)
+        .http_only(http_only);
+    if domain.starts_with('.') {
+        cookie = cookie.domain(host.to_string());
+    }
+    let scheme = if secure { "https" } else { "http" };
+    let url = Url::parse(&format!("{scheme}://{host}{path}"))
+        .map_err(|e| format!("Invalid cookie domain or path '{host}{path}': {e}"))?;
+    store
+        .insert_raw(&cookie.build(), &url)
+        .map_err(|e| format!("The cookie was rejected: {e}"))?;
+    Ok(())
+}
+
+/// Remove every cookie called `name` set for exactly `domain`, returning how many were removed.
+pub fn remove(store: &mut CookieStore, domain: &str, name: &str) -> usize {
+    let before = store.iter_any().count();
+    let kept = store
+        .iter_any()
+        .filter(|c| !(c.name() == name && is_for_domain(c, domain)))
+        .cloned()
+        .collect::<Vec<_>>();
+    let removed = before - kept.len();
+    let Ok(rebuilt) = CookieStore::from_cookies(kept.into_iter().map(Ok::<_, Infallible>), true);
+    *store = rebuilt;
+    removed
+}
+
+/// Write the cookies in the jar, including session cookies, to `path` as JSON.
+pub fn save(store: &CookieStore, path: &str) -> Result<()> {
+    let file =
+        File::create(path).map_err(|e| format!("Failed to create cookie file '{path}': {e}"))?;
+    let mut writer = BufWriter::new(file);
+    cookie_store::serde::json::save_incl_expired_and_nonpersistent(store, &mut writer)
+        .map_err(|e| format!("Failed to write cookie file '{path}': {e}"))?;
+    writer
+        .flush()
+        .map_err(|e| format!("Failed to write cookie file '{path}': {e}"))?;
+    Ok(())
+}
+
+/// Read a jar written by [`save`] from `path`, dropping any cookies that have since expired.
+pub fn load(path: &str) -> Result<CookieStore> {
+    let file = File::open(path).map_err(|e| format!("Failed to open cookie file '{path}': {e}"))?;
+    let store = cookie_store::serde::json::load(BufReader::new(file))
+        .map_err(|e| format!("Cookie file '{path}' is not a valid cookie jar: {e}"))?;
+    Ok(store)
+}
@@ -0,0 +1,239 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+use crate::error::Result;
+
+/// Build the `Authorization` header answering a `WWW-Authenticate: Digest ...` challenge, as
+/// described in RFC 7616. Returns `None` if `challenge` isn't a Digest challenge.
+pub fn authorization(
+    challenge: &str,
+    username: &str,
+    password: &str,
+    method: &str,
+    uri: &str,
+) -> Result<Option<String>> {
+    respond(challenge, username, password, method, uri, &cnonce())
+}
+
+/// [`authorization`] with the client nonce given.
+fn respond(
+    challenge: &str,
+    username: &str,
+    password: &str,
+    method: &str,
+    uri: &str,
+    cnonce: &str,
+) -> Result<Option<String>> {
+    let Some(params) = challenge
+        .trim()
+        .strip_prefix("Digest ")
+        .or_else(|| challenge.trim().strip_prefix("digest "))
+    else {
+        return Ok(None);
+    };
+    let params = parse_params(params);
+    let param = |name: &str| {
+        params
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    };
+
+    let realm = param("realm").unwrap_or_default();
+    let nonce = param("nonce").ok_or("The Digest challenge has no nonce")?;
+    let algorithm = param("algorithm").unwrap_or("MD5");
+    let hash: fn(&str) -> String = match algorithm.to_uppercase().trim_end_matches("-SESS") {
+        "MD5" => |s| hex(&Md5::digest(s)),
+        "SHA-256" => |s| hex(&Sha256::digest(s)),
+        _ => Err(format!("Unsupported Digest algorithm '{algorithm}'"))?,
+    };
+    // auth-int would need the body hashed into the response, which isn't supported
+    let qop = match param("qop") {
+        Some(offered) => match offered.split(',').map(str::trim).find(|q| *q == "auth") {
+            Some(qop) => Some(qop),
+            None => Err(format!(
+                "Unsupported Digest qop '{offered}', only 'auth' is supported"
+            ))?,
+        },
+        None => None,
+    };
+    let nc = "00000001";
+
+    let mut ha1 = hash(&format!("{username}:{realm}:{password}"));
+    if algorithm.to_uppercase().ends_with("-SESS") {
+        ha1 = hash(&format!("{ha1}:{nonce}:{cnonce}"));
+    }
+    let ha2 = hash(&format!("{method}:{uri}"));
+    let response = match qop {
+        Some(qop) => hash(&format!("{ha1}:{nonce}:{nc}:{cnonce}:{qop}:{ha2}")),
+        None => hash(&format!("{ha1}:{nonce}:{ha2}")),
+    };
+
+    let mut header = format!(
+        r#"Digest username="{}", realm="{}", nonce="{}", uri="{}", algorithm={algorithm}, response="{response}""#,
+        escape(username),
+        escape(realm),
+        escape(nonce),
+        escape(uri)
+    );
+    if let Some(qop) = qop {
+        header.push_str(&format!(
+            r#", qop={qop}, nc={nc}, cnonce="{}""#,
+            escape(cnonce)
+        ));
+    }
+    if let Some(opaque) = param("opaque") {
+        header.push_str(&format!(r#", opaque="{}""#, escape(opaque)));
+    }
+    Ok(Some(header))
+}
+
+/// Escape a value for a quoted string in a header.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Split `key=value, key="quoted, value"` pairs, unescaping quoted values.
+fn parse_params(s: &str) -> Vec<(String, String)> {
+    let mut params = vec![];
+    let mut rest = s.trim();
+    while let Some(eq) = rest.find('=') {
+        let key = rest[..eq].trim().to_string();
+        rest = rest[eq + 1..].trim_start();
+        let value;
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let mut unescaped = String::new();
+            let mut end = quoted.len();
+            let mut chars = quoted.char_indices();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '\\' => unescaped.extend(chars.next().map(|(_, escaped)| escaped)),
+                    '"' => {
+                        end = i;
+                        break;
+                    }
+                    c => unescaped.push(c),
+                }
+            }
+            value = unescaped;
+            rest = quoted.get(end + 1..).unwrap_or_default();
+        } else {
+            let end = rest.find(',').unwrap_or(rest.len());
+            value = rest[..end].trim().to_string();
+            rest = &rest[end..];
+        }
+        params.push((key, value));
+        rest = rest.trim_start().trim_start_matches(',').trim_start();
+    }
+    params
+}
+
+/// A client nonce that is unique enough for a test client.
+fn cnonce() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    hex(&Sha256::digest(now.to_string()))[..16].to_string()
+}
+
+/// Encode bytes as lowercase hex.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn answers_md5_challenges() {
+        // The example from RFC 2617, section 3.5
+        let header = respond(
+            r#"Digest realm="testrealm@host.com", qop="auth,auth-int", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", opaque="5ccc069c403ebaf9f0171e9517f40e41""#,
+            "Mufasa",
+            "Circle Of Life",
+            "GET",
+            "/dir/index.html",
+            "0a4f113b",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            header,
+            r#"Digest username="Mufasa", realm="testrealm@host.com", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", uri="/dir/index.html", algorithm=MD5, response="6629fae49393a05397450978507c4ef1", qop=auth, nc=00000001, cnonce="0a4f113b", opaque="5ccc069c403ebaf9f0171e9517f40e41""#
+        );
+    }
+
+    #[test]
+    fn answers_sha_256_challenges() {
+        // The example from RFC 7616, section 3.9.1
+        let header = respond(
+            r#"Digest realm="http-auth@example.org", qop="auth, auth-int", algorithm=SHA-256, nonce="7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v", opaque="FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS""#,
+            "Mufasa",
+            "Circle of Life",
+            "GET",
+            "/dir/index.html",
+            "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ",
+        )
+        .unwrap()
+        .unwrap();
+        assert!(header.contains(
+            r#"response="753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1""#
+        ));
+    }
+
+    #[test]
+    fn answers_challenges_without_qop() {
+        let header = respond(
+            r#"Digest realm="testrealm@host.com", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093""#,
+            "Mufasa",
+            "Circle Of Life",
+            "GET",
+            "/dir/index.html",
+            "0a4f113b",
+        )
+        .unwrap()
+        .unwrap();
+        assert!(header.ends_with(r#"response="670fd8c2df070c60b045671b8b24ff02""#));
+    }
+
+    #[test]
+    fn escapes_quoted_values() {
+        let header = respond(
+            r#"Digest realm="test\"realm", nonce="abc""#,
+            r#"Mu"fa\sa"#,
+            "Circle Of Life",
+            "GET",
+            "/",
+            "0a4f113b",
+        )
+        .unwrap()
+        .unwrap();
+        assert!(header.starts_with(r#"Digest username="Mu\"fa\\sa", realm="test\"realm""#));
+    }
+
+    #[test]
+    fn rejects_unsupported_challenges() {
+        let err = respond(
+            r#"Digest realm="r", nonce="n", qop="auth-int""#,
+            "u",
+            "p",
+            "GET",
+            "/",
+            "c",
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unsupported Digest qop 'auth-int', only 'auth' is supported"
+        );
+        assert!(
+            respond(r#"Basic realm="r""#, "u", "p", "GET", "/", "c")
+                .unwrap()
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,98 @@
+use std::{
+    net::{SocketAddr, ToSocketAddrs},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+use reqwest::dns::{Name, Resolve, Resolving};
+
+/// Which address families connections may use.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum IpFamily {
+    /// IPv4 or IPv6, whichever connects
+    Any,
+    /// Only IPv4
+    V4,
+    /// Only IPv6
+    V6,
+}
+
+impl IpFamily {
+    /// Parse a family from the modes accepted by `SetIpFamily`.
+    pub fn parse(mode: &str) -> Option<Self> {
+        match mode {
+            "any" => Some(Self::Any),
+            "v4" => Some(Self::V4),
+            "v6" => Some(Self::V6),
+            _ => None,
+        }
+    }
+
+    fn allows(self, addr: &SocketAddr) -> bool {
+        match self {
+            Self::Any => true,
+            Self::V4 => addr.is_ipv4(),
+            Self::V6 => addr.is_ipv6(),
+        }
+    }
+}
+
+/// Resolves hostnames with the system resolver, keeping only the addresses in allowed families.
+pub struct FamilyResolver {
+    pub family: IpFamily,
+    /// Where the time taken by the latest lookup is recorded
+    pub lookup_time: Arc<Mutex<Option<Duration>>>,
+}
+
+impl Resolve for FamilyResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let family = self.family;
+        let lookup_time = self.lookup_time.clone();
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let started = Instant::now();
+            // The system resolver blocks, so it runs off the thread that drives every connection
+            let lookup_host = host.clone();
+            let addrs = tokio::task::spawn_blocking(move || {
+                (lookup_host.as_str(), 0)
+                    .to_socket_addrs()
+                    .map(|addrs| addrs.collect::<Vec<_>>())
+            })
+            .await?;
+            *lookup_time.lock() = Some(started.elapsed());
+            let addrs = addrs?
+                .into_iter()
+                .filter(|addr| family.allows(addr))
+                .collect::<Vec<_>>();
+            if addrs.is_empty() {
+                let family = match family {
+                    IpFamily::Any => "",
+                    IpFamily::V4 => "IPv4 ",
+                    IpFamily::V6 => "IPv6 ",
+                };
+                return Err(format!("{host} has no {family}address").into());
+            }
+            Ok(Box::new(addrs.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{client_config::ClientConfig, test_server::TestServer};
+
+    #[test]
+    fn lookups_are_timed_and_filtered_by_family() {
+        let server = TestServer::responding("ok");
+        let config = ClientConfig {
+            ip_family: IpFamily::V4,
+            ..Default::default()
+        };
+        let url = format!("http://localhost:{}/", server.addr.port());
+        let res = config.build().unwrap().get(url).send().unwrap();
+        assert_eq!(res.text().unwrap(), "ok");
+        assert!(config.dns_lookup_time.lock().is_some());
+    }
+}
@@ -0,0 +1,45 @@
+use std::fmt;
+
+/// An error raised by the engine's helpers. Instructions convert it with `?` like any other error.
+#[derive(Debug)]
+pub struct HttpError(String);
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+impl From<String> for HttpError {
+    fn from(value: String) -> Self {
+        Self(value)
+    }
+}
+
+impl From<&str> for HttpError {
+    fn from(value: &str) -> Self {
+        Self(value.to_string())
+    }
+}
+
+impl From<reqwest::Error> for HttpError {
+    fn from(value: reqwest::Error) -> Self {
+        Self(value.to_string())
+    }
+}
+
+impl From<url::ParseError> for HttpError {
+    fn from(value: url::ParseError) -> Self {
+        Self(value.to_string())
+    }
+}
+
+impl From<std::io::Error> for HttpError {
+    fn from(value: std::io::Error) -> Self {
+        Self(value.to_string())
+    }
+}
+
+pub type Result<T> = std::result::Result<T, HttpError>;
@@ -0,0 +1,155 @@
+use serde_json::Value;
+
+use crate::error::Result;
+
+/// How a body is normalized before comparing it to a golden file.
+pub enum Normalization {
+    /// Compared exactly, apart from line endings
+    Exact,
+    /// Runs of whitespace on each line are collapsed, and blank lines at the start and end are
+    /// ignored
+    Whitespace,
+    /// Both are parsed as JSON and compared with sorted keys and consistent formatting
+    Json,
+}
+
+impl Normalization {
+    /// Parse a mode accepted by `AssertBodyEqualsFileNormalized`.
+    pub fn parse(mode: &str) -> Option<Self> {
+        match mode {
+            "exact" => Some(Self::Exact),
+            "whitespace" => Some(Self::Whitespace),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+
+    fn apply(&self, text: &str, what: &str) -> Result<String> {
+        let text = text.replace("\r\n", "\n");
+        match self {
+            Self::Exact => Ok(text),
+            Self::Whitespace => Ok(text
+                .lines()
+                .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+                .collect::<Vec<_>>()
+                .join("\n")
+                .trim_matches('\n')
+                .to_string()),
+            Self::Json => {
+                let json = serde_json::from_str::<Value>(&text)
+                    .map_err(|e| format!("{what} is not valid JSON: {e}"))?;
+                Ok(serde_json::to_string_pretty(&sort_keys(json))
+                    .expect("a JSON value can always be serialized"))
+            }
+        }
+    }
+}
+
+/// Compare a response body to the contents of the golden file at `path`, describing the first
+/// line that differs in the style of a unified diff if they don't match.
+pub fn compare(
+    expected: &str,
+    actual: &str,
+    path: &str,
+    normalization: &Normalization,
+) -> Result<()> {
+    let expected = normalization.apply(expected, &format!("The file {path}"))?;
+    let actual = normalization.apply(actual, "The response body")?;
+    if expected == actual {
+        return Ok(());
+    }
+
+    let mut expected_lines = expected.split('\n');
+    let mut actual_lines = actual.split('\n');
+    let mut line = 1;
+    loop {
+        match (expected_lines.next(), actual_lines.next()) {
+            (Some(e), Some(a)) if e == a => line += 1,
+            (e, a) => {
+                return Err(format!(
+                    "Body did not match {path} at line {line}:\n--- {path}\n+++ response body\n@@ line {line} @@\n-{}\n+{}",
+                    e.unwrap_or("<end of file>"),
+                    a.unwrap_or("<end of body>")
+                )
+                .into());
+            }
+        }
+    }
+}
+
+/// Rebuild a JSON value with the keys of every object in sorted order.
+fn sort_keys(value: Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut entries = map.into_iter().collect::<Vec<_>>();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, sort_keys(value)))
+                    .collect(),
+            )
+        }
+        Value::Array(values) => Value::Array(values.into_iter().map(sort_keys).collect()),
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact_comparison_ignores_line_endings() {
+        compare("a\nb\n", "a\r\nb\r\n", "golden.txt", &Normalization::Exact).unwrap();
+        assert!(compare("a b", "a  b", "golden.txt", &Normalization::Exact).is_err());
+    }
+
+    #[test]
+    fn whitespace_comparison_collapses_runs_and_trims_blank_lines() {
+        compare(
+            "\n  hello   world\n",
+            "hello world\n\n",
+            "golden.txt",
+            &Normalization::Whitespace,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn json_comparison_ignores_key_order_and_formatting() {
+        compare(
+            r#"{"b": [1, {"y": 2, "x": 1}], "a": null}"#,
+            r#"{"a":null,"b":[1,{"x":1,"y":2}]}"#,
+            "golden.json",
+            &Normalization::Json,
+        )
+        .unwrap();
+        assert!(compare("[1, 2]", "[2, 1]", "golden.json", &Normalization::Json).is_err());
+    }
+
+    #[test]
+    fn mismatches_show_the_first_differing_line() {
+        let err = compare("a\nb\nc", "a\nx\nc", "golden.txt", &Normalization::Exact).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Body did not match golden.txt at line 2:\n--- golden.txt\n+++ response body\n@@ line 2 @@\n-b\n+x"
+        );
+    }
+
+    #[test]
+    fn mismatches_show_where_one_side_ends() {
+        let err = compare("a", "a\nb", "golden.txt", &Normalization::Exact).unwrap_err();
+        assert!(err.to_string().ends_with("-<end of file>\n+b"), "{err}");
+    }
+
+    #[test]
+    fn invalid_json_says_which_side_is_invalid() {
+        let err = compare("{}", "{", "golden.json", &Normalization::Json).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("The response body is not valid JSON:"),
+            "{err}"
+        );
+    }
+}
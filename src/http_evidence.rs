@@ -1,9 +1,13 @@
+use encoding_rs::{Encoding, UTF_8};
 use reqwest::{
+    Version,
     blocking::{Request, Response},
-    header::{HeaderMap, HeaderValue},
+    header::{self, HeaderMap, HeaderValue},
 };
 
-pub fn req_to_evidence(req: &Request) -> String {
+/// Render a request for evidence. `body_note` is shown in place of a body that can't be shown,
+/// e.g. one streamed from a file.
+pub fn req_to_evidence(req: &Request, body_note: Option<&str>) -> String {
     let method = req.method();
     let url = req.url().path();
     let query = if let Some(query) = req.url().query() {
@@ -13,6 +17,7 @@ pub fn req_to_evidence(req: &Request) -> String {
     };
     let version = req.version();
     let mut headers = req.headers().clone();
+    // reqwest adds this itself to any request without one, so it's really sent
     if !headers.contains_key("accept") {
         // SAFETY: */* is always valid
         headers.append("accept", HeaderValue::from_str("*/*").unwrap());
@@ -23,28 +28,45 @@ pub fn req_to_evidence(req: &Request) -> String {
         // SAFETY: host is always a valid string
         headers.append("host", HeaderValue::from_str(host).unwrap());
     }
+    if !headers.contains_key("content-length")
+        && let Some(bytes) = req.body().and_then(|b| b.as_bytes())
+    {
+        // reqwest sets the length of any body it knows the size of
+        headers.append("content-length", HeaderValue::from(bytes.len()));
+    }
     let headers = headers_to_evidence(&headers);
     let body = req.body();
     format!(
         "{method} {url}{query} {version:?}\r\n{headers}{}",
         if let Some(body) = body {
-            // SAFETY: requests are never streamed, so this will always be present
-            &format!("\r\n{}", String::from_utf8_lossy(body.as_bytes().unwrap()))
+            &match (body_note, body.as_bytes()) {
+                (Some(note), _) => format!("\r\n<{note}>"),
+                (None, Some(bytes)) => format!("\r\n{}", String::from_utf8_lossy(bytes)),
+                (None, None) => "\r\n<streamed body>".to_string(),
+            }
         } else {
             ""
         }
     )
 }
 
-pub fn res_to_evidence(res: Response, body: &mut String) -> String {
+/// Correct the version in request evidence from [`req_to_evidence`] to the one the request was
+/// actually sent with. HTTP/2 and HTTP/3 are chosen per connection, so a request built as
+/// HTTP/1.1 may go out as either, and only the response shows which was used.
+pub fn with_sent_version(req_ev: String, requested: Version, res: &Response) -> String {
+    let sent = res.version();
+    let negotiated = [Version::HTTP_2, Version::HTTP_3];
+    if sent == requested || !(negotiated.contains(&sent) || negotiated.contains(&requested)) {
+        return req_ev;
+    }
+    let req_ev = req_ev.replacen(&format!(" {requested:?}\r\n"), &format!(" {sent:?}\r\n"), 1);
+    format!("{req_ev}\r\n<sent as {sent:?}, the request was prepared as {requested:?}>")
+}
+
+pub fn res_to_evidence(res: &Response, body: &str) -> String {
     let version = res.version();
     let status = res.status();
     let headers = headers_to_evidence(res.headers());
-    *body = if let Ok(by) = res.bytes() {
-        String::from_utf8(by.to_vec()).unwrap_or("<unable to decode response body>".to_string())
-    } else {
-        "<unable to decode response body>".to_string()
-    };
     format!(
         "{version:?} {status}\r\n{headers}{}",
         if body.is_empty() {
@@ -55,7 +77,69 @@ pub fn res_to_evidence(res: Response, body: &mut String) -> String {
     )
 }
 
-fn headers_to_evidence(headers: &HeaderMap) -> String {
+/// Describe the trailers a response announced in its `Trailer` header, if any. The HTTP client
+/// discards trailer frames, so only their names can be shown, not their values.
+pub fn announced_trailers(res: &Response) -> Option<String> {
+    let names = res
+        .headers()
+        .get_all(header::TRAILER)
+        .iter()
+        .filter_map(|names| names.to_str().ok())
+        .flat_map(|names| names.split(','))
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .collect::<Vec<_>>();
+    if names.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "Announced: {}\r\n<trailer values aren't available from the HTTP client>",
+        names.join(", ")
+    ))
+}
+
+/// The charset parameter of a Content-Type header, if it has one, e.g. "utf-8" for
+/// "text/html; charset=utf-8".
+pub fn charset_param(content_type: &str) -> Option<&str> {
+    content_type
+        .split(';')
+        .skip(1)
+        .filter_map(|param| param.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+        .map(|(_, charset)| charset.trim().trim_matches('"'))
+}
+
+/// Decode a response body for display, using `charset` if one is given, otherwise the charset
+/// `content_type` names. Bodies with neither are read as UTF-8, and bytes that aren't valid in
+/// the charset are replaced. Returns the body and a description of the charset used.
+pub fn decode_body(
+    bytes: &[u8],
+    content_type: Option<&str>,
+    charset: Option<&'static Encoding>,
+) -> (String, String) {
+    let declared = content_type
+        .and_then(charset_param)
+        .and_then(|charset| Encoding::for_label(charset.as_bytes()));
+    let (encoding, source) = match (charset, declared) {
+        (Some(charset), _) => (charset, "set by HTTP.SetResponseCharset"),
+        (None, Some(declared)) => (declared, "from the Content-Type header"),
+        (None, None) => (UTF_8, "the default"),
+    };
+    let (body, used, had_errors) = encoding.decode(bytes);
+    // A byte order mark takes precedence over the charset
+    let source = if used == encoding {
+        source
+    } else {
+        "from the byte order mark"
+    };
+    let mut description = format!("{} ({source})", used.name());
+    if had_errors {
+        description.push_str(", with invalid bytes replaced");
+    }
+    (body.into_owned(), description)
+}
+
+pub fn headers_to_evidence(headers: &HeaderMap) -> String {
     let mut s = String::new();
     for (key, val) in headers {
         if let Ok(val) = val.to_str() {
@@ -66,3 +150,24 @@ fn headers_to_evidence(headers: &HeaderMap) -> String {
     }
     s
 }
+
+/// Render headers as a JSON object, with repeated headers as arrays of their values.
+pub fn headers_to_json(headers: &HeaderMap) -> serde_json::Value {
+    let mut map = serde_json::Map::new();
+    for key in headers.keys() {
+        let mut values = headers
+            .get_all(key)
+            .iter()
+            .map(|val| {
+                serde_json::Value::from(val.to_str().unwrap_or("<header data cannot be displayed>"))
+            })
+            .collect::<Vec<_>>();
+        let value = if values.len() == 1 {
+            values.remove(0)
+        } else {
+            serde_json::Value::Array(values)
+        };
+        map.insert(key.to_string(), value);
+    }
+    serde_json::Value::Object(map)
+}
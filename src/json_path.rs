@@ -0,0 +1,88 @@
+use serde_json::Value;
+
+/// Find the value at `path` in `value`. Paths are dot-separated keys with optional `[n]` array
+/// indices, and may start with `$`, e.g. `$.data.items[0].id` or `data.items.0.id`.
+pub fn lookup<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    let path = path.strip_prefix('$').unwrap_or(path);
+    let mut current = value;
+    for segment in path.split('.').filter(|s| !s.is_empty()) {
+        let (key, indices) = match segment.find('[') {
+            Some(i) => (&segment[..i], &segment[i..]),
+            None => (segment, ""),
+        };
+        if !key.is_empty() {
+            current = match current {
+                Value::Object(map) => map.get(key)?,
+                Value::Array(arr) => arr.get(key.parse::<usize>().ok()?)?,
+                _ => return None,
+            };
+        }
+        for index in indices.split('[').filter(|s| !s.is_empty()) {
+            let index = index.strip_suffix(']')?.parse::<usize>().ok()?;
+            current = current.as_array()?.get(index)?;
+        }
+    }
+    Some(current)
+}
+
+/// Render a JSON value as a plain string: strings without their quotes, anything else as JSON.
+pub fn to_plain_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Whether `value` matches the `expected` string. Numbers compare numerically, so "1" matches
+/// both `1` and `1.0`.
+pub fn matches(value: &Value, expected: &str) -> bool {
+    if let Value::Number(n) = value
+        && let (Some(actual), Ok(expected)) = (n.as_f64(), expected.trim().parse::<f64>())
+    {
+        return actual == expected;
+    }
+    to_plain_string(value) == expected
+}
+
+/// Whether two values are equal. Numbers compare numerically, so `1` equals `1.0`.
+pub fn equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64() == b.as_f64(),
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn looks_up_keys_and_indices() {
+        let value = json!({"data": {"items": [{"id": 7}, {"id": 8, "tags": ["a", "b"]}]}});
+        assert_eq!(lookup(&value, "$.data.items[0].id"), Some(&json!(7)));
+        assert_eq!(lookup(&value, "data.items.1.id"), Some(&json!(8)));
+        assert_eq!(lookup(&value, "$.data.items[1].tags[1]"), Some(&json!("b")));
+        assert_eq!(lookup(&value, "$"), Some(&value));
+    }
+
+    #[test]
+    fn missing_paths_find_nothing() {
+        let value = json!({"data": {"items": [{"id": 7}]}});
+        assert_eq!(lookup(&value, "$.data.missing"), None);
+        assert_eq!(lookup(&value, "$.data.items[3]"), None);
+        assert_eq!(lookup(&value, "$.data.items[0].id.deeper"), None);
+    }
+
+    #[test]
+    fn numbers_match_numerically() {
+        assert!(matches(&json!(1.0), "1"));
+        assert!(matches(&json!(1), " 1.0"));
+        assert!(!matches(&json!(1), "2"));
+        assert!(matches(&json!("text"), "text"));
+        assert!(matches(&json!(true), "true"));
+        assert!(equal(&json!(1), &json!(1.0)));
+        assert!(!equal(&json!("1"), &json!(1)));
+    }
+}
@@ -0,0 +1,74 @@
+use serde_json::Value;
+
+use crate::error::Result;
+
+/// Validate `instance` against the JSON Schema in `schema`. A schema that can't be used is
+/// reported separately from an instance that doesn't match it, which lists every failure.
+pub fn validate(schema: &str, instance: &Value) -> Result<()> {
+    let schema: Value =
+        serde_json::from_str(schema).map_err(|e| format!("The schema is not valid JSON: {e}"))?;
+    let validator = jsonschema::validator_for(&schema)
+        .map_err(|e| format!("The schema is not a valid JSON Schema: {e}"))?;
+
+    let failures = validator
+        .iter_errors(instance)
+        .map(|e| {
+            let path = e.instance_path.to_string();
+            let path = if path.is_empty() { "/" } else { &path };
+            format!("{path}: {e}")
+        })
+        .collect::<Vec<_>>();
+    if !failures.is_empty() {
+        Err(format!(
+            "The response does not match the schema:\n{}",
+            failures.join("\n")
+        ))?
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const USER: &str = r#"{
+        "type": "object",
+        "required": ["id", "name"],
+        "properties": {"id": {"type": "integer"}, "name": {"type": "string"}}
+    }"#;
+
+    #[test]
+    fn matching_documents_pass() {
+        validate(USER, &json!({"id": 1, "name": "Ada"})).unwrap();
+    }
+
+    #[test]
+    fn mismatches_list_every_failure_with_its_path() {
+        let err = validate(USER, &json!({"id": "one"}))
+            .unwrap_err()
+            .to_string();
+        let (summary, failures) = err.split_once('\n').unwrap();
+        assert_eq!(summary, "The response does not match the schema:");
+        let failures = failures.lines().collect::<Vec<_>>();
+        assert_eq!(failures.len(), 2, "{err}");
+        assert!(failures.iter().any(|f| f.starts_with("/id: ")), "{err}");
+        assert!(failures.iter().any(|f| f.starts_with("/: ")), "{err}");
+    }
+
+    #[test]
+    fn schemas_that_fail_to_compile_are_reported_separately() {
+        let err = validate(r#"{"type": "not-a-type"}"#, &json!({})).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("The schema is not a valid JSON Schema:"),
+            "{err}"
+        );
+        let err = validate("{", &json!({})).unwrap_err();
+        assert!(
+            err.to_string().starts_with("The schema is not valid JSON:"),
+            "{err}"
+        );
+    }
+}
@@ -0,0 +1,94 @@
+use base64::Engine as _;
+use serde_json::Value;
+
+use crate::error::Result;
+
+/// Decode the header and payload of a JWT, without verifying its signature, as a pretty-printed
+/// JSON object with `header` and `payload` fields. A leading "Bearer " is ignored, so the value
+/// of an Authorization header can be given as-is.
+pub fn decode(token: &str) -> Result<String> {
+    let token = token.trim();
+    let token = token.strip_prefix("Bearer ").unwrap_or(token).trim();
+    let segments = token.split('.').collect::<Vec<_>>();
+    if segments.len() != 3 {
+        return Err(format!(
+            "A JWT has 3 segments separated by '.', but this token has {}",
+            segments.len()
+        )
+        .into());
+    }
+    let header = decode_segment(segments[0], "header")?;
+    let payload = decode_segment(segments[1], "payload")?;
+    Ok(
+        serde_json::to_string_pretty(&serde_json::json!({ "header": header, "payload": payload }))
+            .expect("a JSON value can always be serialized"),
+    )
+}
+
+/// Decode one base64url-encoded JSON segment of a JWT.
+fn decode_segment(segment: &str, name: &str) -> Result<Value> {
+    let bytes = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(segment.trim_end_matches('='))
+        .map_err(|e| format!("The JWT {name} is not valid base64url: {e}"))?;
+    serde_json::from_slice(&bytes)
+        .map_err(|e| format!("The JWT {name} is not valid JSON: {e}").into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKEN: &str =
+        "eyJhbGciOiJIUzI1NiIsInR5cCI6IkpXVCJ9.eyJzdWIiOiIxMjM0IiwiYWRtaW4iOnRydWV9.sig";
+
+    fn decoded(token: &str) -> Value {
+        serde_json::from_str(&decode(token).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn tokens_decode_to_their_header_and_payload() {
+        assert_eq!(
+            decoded(TOKEN),
+            serde_json::json!({
+                "header": {"alg": "HS256", "typ": "JWT"},
+                "payload": {"sub": "1234", "admin": true},
+            })
+        );
+    }
+
+    #[test]
+    fn bearer_prefixes_are_ignored() {
+        assert_eq!(decoded(&format!(" Bearer {TOKEN} ")), decoded(TOKEN));
+    }
+
+    #[test]
+    fn segments_use_the_url_safe_alphabet_with_optional_padding() {
+        let token = "eyJhbGciOiJub25lIn0.eyJzdWIiOiI_PiJ9==.";
+        assert_eq!(decoded(token)["payload"]["sub"], "?>");
+    }
+
+    #[test]
+    fn tokens_need_three_segments() {
+        let err = decode("eyJhbGciOiJub25lIn0.eyJzdWIiOiI_PiJ9").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "A JWT has 3 segments separated by '.', but this token has 2"
+        );
+    }
+
+    #[test]
+    fn invalid_segments_are_named() {
+        let err = decode("eyJhbGciOiJub25lIn0.not*base64.sig").unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("The JWT payload is not valid base64url:"),
+            "{err}"
+        );
+        let err = decode("bm90IGpzb24.e30.sig").unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("The JWT header is not valid JSON:"),
+            "{err}"
+        );
+    }
+}
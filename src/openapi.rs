@@ -0,0 +1,250 @@
+use percent_encoding::{AsciiSet, CONTROLS, utf8_percent_encode};
+use reqwest::Method;
+use serde_json::{Map, Value};
+
+use crate::{error::Result, json_path::to_plain_string};
+
+/// Characters percent-encoded in a path segment.
+pub const PATH_SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// The HTTP methods an OpenAPI path item can define operations for.
+const METHODS: &[&str] = &[
+    "get", "put", "post", "delete", "options", "head", "patch", "trace",
+];
+
+/// A request resolved from an OpenAPI operation.
+pub struct Operation {
+    pub method: Method,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<String>,
+}
+
+/// Resolve the operation `operation_id` from `spec` into a request, filling in its parameters from
+/// `params`. The request body, if any, is taken from the `body` key of `params`.
+pub fn resolve(spec: &Value, operation_id: &str, params: &Map<String, Value>) -> Result<Operation> {
+    let paths = spec
+        .get("paths")
+        .and_then(Value::as_object)
+        .ok_or("The OpenAPI spec has no paths")?;
+
+    for (path, item) in paths {
+        for method in METHODS {
+            let Some(op) = item.get(*method) else {
+                continue;
+            };
+            if op.get("operationId").and_then(Value::as_str) != Some(operation_id) {
+                continue;
+            }
+
+            // Operation parameters override path-level parameters with the same name and location
+            let mut declared: Vec<&Value> = vec![];
+            let path_params = item.get("parameters").and_then(Value::as_array);
+            let op_params = op.get("parameters").and_then(Value::as_array);
+            for param in path_params.into_iter().chain(op_params).flatten() {
+                let param = resolve_ref(spec, param);
+                declared.retain(|p| {
+                    p.get("name") != param.get("name") || p.get("in") != param.get("in")
+                });
+                declared.push(param);
+            }
+
+            let mut missing = vec![];
+            let mut resolved_path = path.clone();
+            let mut query = url::form_urlencoded::Serializer::new(String::new());
+            let mut has_query = false;
+            let mut headers = vec![];
+            for param in declared {
+                let name = param
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                let location = param.get("in").and_then(Value::as_str).unwrap_or_default();
+                let required = location == "path"
+                    || param
+                        .get("required")
+                        .and_then(Value::as_bool)
+                        .unwrap_or(false);
+                let Some(value) = params.get(name).map(to_plain_string) else {
+                    if required {
+                        missing.push(name.to_string());
+                    }
+                    continue;
+                };
+                match location {
+                    "path" => {
+                        let encoded = utf8_percent_encode(&value, PATH_SEGMENT).to_string();
+                        resolved_path = resolved_path.replace(&format!("{{{name}}}"), &encoded);
+                    }
+                    "query" => {
+                        query.append_pair(name, &value);
+                        has_query = true;
+                    }
+                    "header" => headers.push((name.to_string(), value)),
+                    "cookie" => headers.push(("cookie".to_string(), format!("{name}={value}"))),
+                    _ => (),
+                }
+            }
+
+            let body = params.get("body").map(|body| body.to_string());
+            let body_required = op
+                .get("requestBody")
+                .map(|b| resolve_ref(spec, b))
+                .and_then(|b| b.get("required"))
+                .and_then(Value::as_bool)
+                .unwrap_or(false);
+            if body_required && body.is_none() {
+                missing.push("body".to_string());
+            }
+            if !missing.is_empty() {
+                Err(format!(
+                    "Missing required parameters for operation '{operation_id}': {}",
+                    missing.join(", ")
+                ))?
+            }
+            if body.is_some() {
+                headers.push(("content-type".to_string(), "application/json".to_string()));
+            }
+
+            let server = spec
+                .pointer("/servers/0/url")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .trim_end_matches('/');
+            let mut url = format!("{server}{resolved_path}");
+            if has_query {
+                url.push('?');
+                url.push_str(&query.finish());
+            }
+
+            return Ok(Operation {
+                method: Method::from_bytes(method.to_uppercase().as_bytes())
+                    .map_err(|e| e.to_string())?,
+                url,
+                headers,
+                body,
+            });
+        }
+    }
+
+    Err(format!(
+        "Operation '{operation_id}' was not found in the OpenAPI spec"
+    ))?
+}
+
+/// Follow a local `$ref` (e.g. `#/components/parameters/id`), returning the value itself if it
+/// isn't a reference or the reference can't be followed.
+fn resolve_ref<'a>(spec: &'a Value, value: &'a Value) -> &'a Value {
+    value
+        .get("$ref")
+        .and_then(Value::as_str)
+        .and_then(|r| r.strip_prefix('#'))
+        .and_then(|pointer| spec.pointer(pointer))
+        .unwrap_or(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn spec() -> Value {
+        json!({
+            "servers": [{"url": "https://api.example.com/v1/"}],
+            "components": {
+                "parameters": {
+                    "id": {"name": "id", "in": "path", "required": true}
+                }
+            },
+            "paths": {
+                "/users/{id}": {
+                    "parameters": [{"$ref": "#/components/parameters/id"}],
+                    "get": {
+                        "operationId": "getUser",
+                        "parameters": [
+                            {"name": "expand", "in": "query"},
+                            {"name": "X-Tenant", "in": "header", "required": true}
+                        ]
+                    },
+                    "put": {
+                        "operationId": "updateUser",
+                        "requestBody": {"required": true}
+                    }
+                }
+            }
+        })
+    }
+
+    fn params(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn resolves_path_query_and_header_parameters() {
+        let op = resolve(
+            &spec(),
+            "getUser",
+            &params(json!({"id": "a b/c", "expand": "roles", "X-Tenant": 7})),
+        )
+        .unwrap();
+        assert_eq!(op.method, Method::GET);
+        assert_eq!(
+            op.url,
+            "https://api.example.com/v1/users/a%20b%2Fc?expand=roles"
+        );
+        assert_eq!(op.headers, vec![("X-Tenant".to_string(), "7".to_string())]);
+        assert!(op.body.is_none());
+    }
+
+    #[test]
+    fn reports_every_missing_required_parameter() {
+        let err = resolve(&spec(), "getUser", &Map::new()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Missing required parameters for operation 'getUser': id, X-Tenant"
+        );
+        let err = resolve(&spec(), "updateUser", &params(json!({"id": 1}))).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Missing required parameters for operation 'updateUser': body"
+        );
+    }
+
+    #[test]
+    fn sends_the_body_as_json() {
+        let op = resolve(
+            &spec(),
+            "updateUser",
+            &params(json!({"id": 1, "body": {"name": "Ada"}})),
+        )
+        .unwrap();
+        assert_eq!(op.method, Method::PUT);
+        assert_eq!(op.url, "https://api.example.com/v1/users/1");
+        assert_eq!(op.body.as_deref(), Some(r#"{"name":"Ada"}"#));
+        assert!(
+            op.headers
+                .contains(&("content-type".to_string(), "application/json".to_string()))
+        );
+    }
+
+    #[test]
+    fn unknown_operations_are_an_error() {
+        let err = resolve(&spec(), "deleteUser", &Map::new()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Operation 'deleteUser' was not found in the OpenAPI spec"
+        );
+    }
+}
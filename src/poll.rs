@@ -0,0 +1,79 @@
+use std::time::Duration;
+
+use reqwest::StatusCode;
+
+use crate::{error::Result, status_matches};
+
+/// What `PollUntil` waits for.
+pub enum Condition {
+    /// The status matches, or with `equal` false doesn't match, a code like 200 or a class like 2xx
+    Status { pattern: String, equal: bool },
+    /// The body contains this text
+    BodyContains(String),
+}
+
+impl Condition {
+    /// Parse "status == 200", "status != 202" or "body contains <text>".
+    pub fn parse(condition: &str) -> Result<Self> {
+        let invalid = || {
+            format!(
+                "Invalid condition '{condition}', expected 'status == <code>', 'status != <code>' or 'body contains <text>'"
+            )
+        };
+        let trimmed = condition.trim();
+        if let Some(text) = trimmed.strip_prefix("body contains ") {
+            return Ok(Self::BodyContains(text.to_string()));
+        }
+        let Some(comparison) = trimmed.strip_prefix("status") else {
+            return Err(invalid().into());
+        };
+        let comparison = comparison.trim_start();
+        let (equal, pattern) = if let Some(pattern) = comparison.strip_prefix("==") {
+            (true, pattern.trim())
+        } else if let Some(pattern) = comparison.strip_prefix("!=") {
+            (false, pattern.trim())
+        } else {
+            return Err(invalid().into());
+        };
+        status_matches(pattern, StatusCode::OK)?;
+        Ok(Self::Status {
+            pattern: pattern.to_string(),
+            equal,
+        })
+    }
+
+    /// Whether a response meets the condition.
+    pub fn is_met(&self, status: StatusCode, body: &str) -> bool {
+        match self {
+            Self::Status { pattern, equal } => {
+                status_matches(pattern, status).unwrap_or(false) == *equal
+            }
+            Self::BodyContains(text) => body.contains(text.as_str()),
+        }
+    }
+}
+
+/// The outcome of polling a URL.
+pub struct Polled {
+    /// The body of the last response
+    pub body: String,
+    /// How many requests were sent
+    pub attempts: u32,
+    /// How long polling took
+    pub elapsed: Duration,
+    /// Whether the last response met the condition, rather than the timeout passing
+    pub met: bool,
+}
+
+impl Polled {
+    /// The result returned by the polling instructions, with the status of the last response.
+    pub fn to_json(&self, status: StatusCode) -> String {
+        serde_json::json!({
+            "body": self.body,
+            "status": status.as_u16(),
+            "attempts": self.attempts,
+            "elapsed_ms": u64::try_from(self.elapsed.as_millis()).unwrap_or(u64::MAX),
+        })
+        .to_string()
+    }
+}
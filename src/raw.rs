@@ -0,0 +1,344 @@
+use std::{
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use native_tls::TlsStream;
+use reqwest::{
+    StatusCode,
+    header::{self, HeaderMap, HeaderName, HeaderValue},
+};
+
+use crate::{client_config::ClientConfig, error::Result, tls};
+
+/// How long to wait for the server to start responding before giving up on a raw exchange.
+const RAW_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long the server may go quiet once it has started responding, for responses that don't
+/// say where they end.
+const RAW_IDLE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Write `request` verbatim to `host:port`, optionally over TLS, and read back the response,
+/// stopping once it's complete, the server closes the connection or it goes quiet. Connecting
+/// gives up after the configured connect timeout. The connection is always made directly, never
+/// through a proxy, but TLS trusts the configured root certificates and checks any pins.
+pub fn send(
+    host: &str,
+    port: u16,
+    tls: bool,
+    config: &ClientConfig,
+    request: &[u8],
+) -> Result<Vec<u8>> {
+    let connect_timeout = config.connect_timeout;
+    let stream = connect(host, port, connect_timeout).map_err(|e| match e.kind() {
+        io::ErrorKind::TimedOut => format!(
+            "Timed out connecting to {host}:{port} after {}ms",
+            connect_timeout.unwrap_or_default().as_millis()
+        ),
+        _ => format!("Failed to connect to {host}:{port}: {e}"),
+    })?;
+    stream.set_read_timeout(Some(RAW_TIMEOUT))?;
+    stream.set_write_timeout(Some(RAW_TIMEOUT))?;
+    if tls {
+        let mut builder = native_tls::TlsConnector::builder();
+        builder
+            .danger_accept_invalid_certs(config.accept_invalid_certs)
+            .danger_accept_invalid_hostnames(config.accept_invalid_hostnames);
+        for cert in &config.root_certificates {
+            builder.add_root_certificate(
+                native_tls::Certificate::from_der(cert)
+                    .map_err(|e| format!("Invalid root certificate: {e}"))?,
+            );
+        }
+        let connector = builder
+            .build()
+            .map_err(|e| format!("Failed to set up TLS: {e}"))?;
+        let stream = connector
+            .connect(host, stream)
+            .map_err(|e| format!("TLS handshake with {host}:{port} failed: {e}"))?;
+        check_pins(&stream, host, config)?;
+        Ok(exchange(stream, request)?)
+    } else {
+        Ok(exchange(stream, request)?)
+    }
+}
+
+/// Fail if `host` has pinned certificates and the one `stream` was set up with isn't among them,
+/// before anything has been sent.
+fn check_pins(stream: &TlsStream<TcpStream>, host: &str, config: &ClientConfig) -> Result<()> {
+    let Some(pins) = config.pins.get(&host.to_lowercase()) else {
+        return Ok(());
+    };
+    let cert = stream
+        .peer_certificate()
+        .map_err(|e| format!("Failed to read the certificate of {host}: {e}"))?
+        .ok_or(format!("{host} didn't present a certificate"))?;
+    let der = cert
+        .to_der()
+        .map_err(|e| format!("Failed to read the certificate of {host}: {e}"))?;
+    let actual = tls::spki_sha256(&der)?;
+    if !pins.contains(&actual) {
+        return Err(format!(
+            "Certificate pin mismatch for {host}: expected {}, got {actual}",
+            pins.join(" or ")
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// A connection a raw exchange is made over.
+trait RawStream: Read + Write {
+    /// The TCP connection underneath, for changing its timeouts.
+    fn tcp(&self) -> &TcpStream;
+}
+
+impl RawStream for TcpStream {
+    fn tcp(&self) -> &TcpStream {
+        self
+    }
+}
+
+impl RawStream for TlsStream<TcpStream> {
+    fn tcp(&self) -> &TcpStream {
+        self.get_ref()
+    }
+}
+
+/// Connect to the first address of `host` that accepts a connection within `timeout`.
+fn connect(host: &str, port: u16, timeout: Option<Duration>) -> io::Result<TcpStream> {
+    let Some(timeout) = timeout else {
+        return TcpStream::connect((host, port));
+    };
+    let mut last_error = None;
+    for addr in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, format!("{host} has no address"))
+    }))
+}
+
+fn exchange(mut stream: impl RawStream, request: &[u8]) -> io::Result<Vec<u8>> {
+    stream.write_all(request)?;
+    stream.flush()?;
+    let head_request = request.starts_with(b"HEAD ");
+    let mut response = Vec::new();
+    let mut buf = [0; 8192];
+    loop {
+        match stream.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                if response.is_empty() {
+                    stream.tcp().set_read_timeout(Some(RAW_IDLE_TIMEOUT))?;
+                }
+                response.extend_from_slice(&buf[..n]);
+                // Keep-alive servers hold the connection open after a response
+                if is_complete(&response, head_request) {
+                    break;
+                }
+            }
+            // A server holding the connection open has sent all it is going to
+            Err(e)
+                if !response.is_empty()
+                    && matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+            {
+                break;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(response)
+}
+
+/// Whether `raw` holds a whole response, as far as its headers say where it ends. Responses
+/// that end when the connection closes are never complete.
+fn is_complete(raw: &[u8], head_request: bool) -> bool {
+    let Some((status, headers, offset)) = parse_response(raw) else {
+        return false;
+    };
+    // An interim response is followed by the real one
+    if status.is_informational() {
+        return false;
+    }
+    let body = &raw[offset..];
+    if head_request || status == StatusCode::NO_CONTENT || status == StatusCode::NOT_MODIFIED {
+        return true;
+    }
+    let chunked = headers
+        .get_all(header::TRANSFER_ENCODING)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .any(|v| v.to_ascii_lowercase().contains("chunked"));
+    if chunked {
+        return chunked_body_complete(body);
+    }
+    match headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<usize>().ok())
+    {
+        Some(length) => body.len() >= length,
+        None => false,
+    }
+}
+
+/// Whether a chunked body has reached its last chunk and the end of its trailers.
+fn chunked_body_complete(mut body: &[u8]) -> bool {
+    loop {
+        let Some(line_end) = body.windows(2).position(|w| w == b"\r\n") else {
+            return false;
+        };
+        let size = String::from_utf8_lossy(&body[..line_end]);
+        let size = size.split(';').next().unwrap_or_default().trim();
+        let Ok(size) = usize::from_str_radix(size, 16) else {
+            return false;
+        };
+        body = &body[line_end + 2..];
+        if size == 0 {
+            // Trailers, if any, end with an empty line
+            return body.starts_with(b"\r\n") || body.windows(4).any(|w| w == b"\r\n\r\n");
+        }
+        if body.len() < size + 2 {
+            return false;
+        }
+        body = &body[size + 2..];
+    }
+}
+
+/// Render the bytes of a raw exchange for evidence without altering any of them: as text if
+/// they're valid UTF-8, otherwise base64-encoded.
+pub fn to_evidence(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => format!(
+            "<{} bytes that aren't valid UTF-8, base64-encoded>\r\n{}",
+            bytes.len(),
+            STANDARD.encode(bytes)
+        ),
+    }
+}
+
+/// Parse the status line and headers of a raw response, returning them along with the offset the
+/// body starts at. Returns `None` if the response isn't well-formed HTTP.
+pub fn parse_response(raw: &[u8]) -> Option<(StatusCode, HeaderMap, usize)> {
+    let mut headers = [httparse::EMPTY_HEADER; 128];
+    let mut res = httparse::Response::new(&mut headers);
+    let httparse::Status::Complete(offset) = res.parse(raw).ok()? else {
+        return None;
+    };
+    let status = StatusCode::from_u16(res.code?).ok()?;
+    let mut map = HeaderMap::new();
+    for h in res.headers.iter() {
+        map.append(
+            HeaderName::from_bytes(h.name.as_bytes()).ok()?,
+            HeaderValue::from_bytes(h.value).ok()?,
+        );
+    }
+    Some((status, map, offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, time::Instant};
+
+    use super::*;
+    use crate::test_server::{self, TestServer};
+
+    const GET: &[u8] = b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+
+    #[test]
+    fn keep_alive_responses_return_once_complete() {
+        let server = TestServer::responding("ok");
+        let started = Instant::now();
+        let config = ClientConfig::default();
+        let response = send("127.0.0.1", server.addr.port(), false, &config, GET).unwrap();
+        assert!(response.ends_with(b"\r\n\r\nok"));
+        assert!(started.elapsed() < RAW_IDLE_TIMEOUT);
+    }
+
+    #[test]
+    fn responses_end_by_content_length_or_last_chunk() {
+        assert!(is_complete(
+            b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok",
+            false
+        ));
+        assert!(!is_complete(
+            b"HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\nok",
+            false
+        ));
+        let chunked = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nok\r\n";
+        assert!(!is_complete(chunked, false));
+        assert!(is_complete(&[&chunked[..], b"0\r\n\r\n"].concat(), false));
+        assert!(is_complete(
+            &[&chunked[..], b"0\r\nX-Sum: 1\r\n\r\n"].concat(),
+            false
+        ));
+        assert!(is_complete(b"HTTP/1.1 204 No Content\r\n\r\n", false));
+        assert!(is_complete(
+            b"HTTP/1.1 200 OK\r\nContent-Length: 9\r\n\r\n",
+            true
+        ));
+        assert!(!is_complete(b"HTTP/1.1 100 Continue\r\n\r\n", false));
+        assert!(!is_complete(b"HTTP/1.1 200 OK\r\n\r\nuntil close", false));
+    }
+
+    #[test]
+    fn tls_trusts_configured_roots() {
+        let server = TestServer::tls_responding("secure");
+        let config = ClientConfig {
+            root_certificates: vec![test_server::ca_cert()],
+            ..Default::default()
+        };
+        let response = send("localhost", server.addr.port(), true, &config, GET).unwrap();
+        assert!(response.ends_with(b"secure"));
+    }
+
+    #[test]
+    fn tls_checks_pins_before_sending() {
+        let server = TestServer::tls_responding("secure");
+        let config = ClientConfig {
+            root_certificates: vec![test_server::ca_cert()],
+            pins: HashMap::from([("localhost".to_string(), vec![STANDARD.encode([0; 32])])]),
+            ..Default::default()
+        };
+        let err = send("localhost", server.addr.port(), true, &config, GET).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("Certificate pin mismatch for localhost"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn evidence_keeps_text_as_sent() {
+        let raw = "HTTP/1.1 200 OK\r\nX-Odd:  spaced \r\n\r\nbody";
+        assert_eq!(to_evidence(raw.as_bytes()), raw);
+    }
+
+    #[test]
+    fn evidence_encodes_binary_as_base64() {
+        let raw = b"HTTP/1.1 200 OK\r\n\r\n\xff\x00";
+        let evidence = to_evidence(raw);
+        let (note, encoded) = evidence.split_once("\r\n").unwrap();
+        assert_eq!(note, "<21 bytes that aren't valid UTF-8, base64-encoded>");
+        assert_eq!(STANDARD.decode(encoded).unwrap(), raw);
+    }
+
+    #[test]
+    fn parses_the_response_head() {
+        let raw = b"HTTP/1.1 404 Not Found\r\nContent-Length: 4\r\n\r\nnope";
+        let (status, headers, offset) = parse_response(raw).unwrap();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(headers["content-length"], "4");
+        assert_eq!(&raw[offset..], b"nope");
+        assert!(parse_response(b"not http").is_none());
+    }
+}
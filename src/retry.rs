@@ -0,0 +1,114 @@
+use std::{
+    hash::{BuildHasher, RandomState},
+    time::{Duration, SystemTime},
+};
+
+use reqwest::StatusCode;
+
+use crate::{error::Result, status_matches};
+
+/// The backoff used when retrying without one being given.
+pub const DEFAULT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Which failed attempts `SendWithRetry` tries again.
+pub struct RetryOn {
+    /// Whether to retry when no response arrived, e.g. for refused or reset connections
+    connection: bool,
+    /// The status codes like 503 and classes like 5xx to retry
+    statuses: Vec<String>,
+}
+
+impl RetryOn {
+    /// Parse a comma-separated list of "connection", status codes and classes, e.g.
+    /// "connection,5xx,429".
+    pub fn parse(retry_on: &str) -> Result<Self> {
+        let mut connection = false;
+        let mut statuses = vec![];
+        for part in retry_on.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            if part.eq_ignore_ascii_case("connection") {
+                connection = true;
+            } else {
+                status_matches(part, StatusCode::OK)?;
+                statuses.push(part.to_string());
+            }
+        }
+        if !connection && statuses.is_empty() {
+            return Err(
+                "Nothing to retry on, expected connection, codes like 503 or classes like 5xx"
+                    .into(),
+            );
+        }
+        Ok(Self {
+            connection,
+            statuses,
+        })
+    }
+
+    /// Whether to retry an attempt that failed before any response arrived.
+    pub fn connection(&self) -> bool {
+        self.connection
+    }
+
+    /// Whether to retry an attempt that got a response with `status`.
+    pub fn status(&self, status: StatusCode) -> bool {
+        self.statuses
+            .iter()
+            .any(|pattern| status_matches(pattern, status).unwrap_or(false))
+    }
+}
+
+/// How long to wait before the `retry`th retry: `backoff` doubled for each earlier retry, plus up
+/// to half as much again at random so clients that failed together don't retry together.
+pub fn delay(backoff: Duration, retry: u32) -> Duration {
+    let delay = backoff.saturating_mul(1 << retry.saturating_sub(1).min(16));
+    let max_jitter = u64::try_from(delay.as_millis() / 2).unwrap_or(u64::MAX);
+    // A randomly seeded hasher is the standard library's only source of randomness
+    let jitter = RandomState::new().hash_one(retry) % max_jitter.saturating_add(1);
+    delay.saturating_add(Duration::from_millis(jitter))
+}
+
+/// Parse a Retry-After header, which is either a number of seconds or an HTTP-date. Returns
+/// `None` if it's neither.
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    // A date that has already passed means the request can be retried straight away
+    Some(date.duration_since(SystemTime::now()).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_after_accepts_seconds() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after(" 0 "), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn retry_after_accepts_future_dates() {
+        let date = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(120));
+        let wait = parse_retry_after(&date).unwrap();
+        // HTTP-dates only have whole seconds
+        assert!(wait > Duration::from_secs(118) && wait <= Duration::from_secs(120));
+    }
+
+    #[test]
+    fn retry_after_dates_in_the_past_mean_no_wait() {
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn invalid_retry_after_values_are_ignored() {
+        for value in ["", "soon", "-5", "1.5"] {
+            assert_eq!(parse_retry_after(value), None, "{value}");
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use std::{io::BufRead, time::Instant};
+
+use serde_json::{Value, json};
+
+use crate::error::Result;
+
+/// Read up to `max_events` Server-Sent Events from `reader`, stopping early if the stream closes.
+/// Returns the events as JSON objects with `event`, `data` and (if set) `id` keys, along with the
+/// raw stream text that was read.
+pub fn read_events<R: BufRead>(
+    mut reader: R,
+    max_events: usize,
+    deadline: Instant,
+) -> Result<(Vec<Value>, String)> {
+    let mut events = vec![];
+    let mut raw = String::new();
+    let mut event_type = String::new();
+    let mut data: Vec<String> = vec![];
+    let mut id = None;
+
+    while events.len() < max_events {
+        if Instant::now() > deadline {
+            Err(format!(
+                "Timed out waiting for events, received {} of {max_events}",
+                events.len()
+            ))?
+        }
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+        raw.push_str(&line);
+        let line = line.trim_end_matches(['\r', '\n']);
+
+        if line.is_empty() {
+            // A blank line dispatches the event, if it has any data
+            if !data.is_empty() {
+                let event_type = match std::mem::take(&mut event_type) {
+                    t if t.is_empty() => "message".to_string(),
+                    t => t,
+                };
+                let mut event = json!({
+                    "event": event_type,
+                    "data": data.join("\n"),
+                });
+                if let Some(id) = &id {
+                    event["id"] = json!(id);
+                }
+                events.push(event);
+            }
+            data.clear();
+            event_type.clear();
+            continue;
+        }
+        if line.starts_with(':') {
+            continue;
+        }
+
+        let (field, value) = line.split_once(':').unwrap_or((line, ""));
+        let value = value.strip_prefix(' ').unwrap_or(value);
+        match field {
+            "event" => event_type = value.to_string(),
+            "data" => data.push(value.to_string()),
+            "id" => id = Some(value.to_string()),
+            _ => (),
+        }
+    }
+
+    Ok((events, raw))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn far_future() -> Instant {
+        Instant::now() + Duration::from_secs(60)
+    }
+
+    #[test]
+    fn parses_events() {
+        let stream = ": keepalive\n\nevent: update\ndata: line 1\ndata: line 2\nid: 7\n\ndata:no space\r\n\r\n";
+        let (events, raw) = read_events(stream.as_bytes(), 10, far_future()).unwrap();
+        assert_eq!(
+            events,
+            vec![
+                json!({"event": "update", "data": "line 1\nline 2", "id": "7"}),
+                // The last ID carries over to later events
+                json!({"event": "message", "data": "no space", "id": "7"}),
+            ]
+        );
+        assert_eq!(raw, stream);
+    }
+
+    #[test]
+    fn stops_after_max_events() {
+        let stream = "data: 1\n\ndata: 2\n\ndata: 3\n\n";
+        let (events, raw) = read_events(stream.as_bytes(), 2, far_future()).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(raw, "data: 1\n\ndata: 2\n\n");
+    }
+
+    #[test]
+    fn events_without_data_are_skipped() {
+        let stream = "event: ping\n\ndata: real\n\n";
+        let (events, _) = read_events(stream.as_bytes(), 10, far_future()).unwrap();
+        assert_eq!(events, vec![json!({"event": "message", "data": "real"})]);
+    }
+
+    #[test]
+    fn times_out_at_the_deadline() {
+        let past = Instant::now() - Duration::from_millis(1);
+        let err = read_events("data: 1\n\n".as_bytes(), 1, past).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Timed out waiting for events, received 0 of 1"
+        );
+    }
+}
@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+
+use crate::error::Result;
+
+/// Replace every `{{name}}` token in `input` with the value of the variable `name`. Any token left
+/// unresolved is an error, so typos in variable names are caught before a request is sent.
+pub fn substitute(input: &str, variables: &HashMap<String, String>) -> Result<String> {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start..].find("}}") else {
+            break;
+        };
+        output.push_str(&rest[..start]);
+        let token = &rest[start..start + len + 2];
+        let name = token[2..token.len() - 2].trim();
+        match variables.get(name) {
+            Some(value) => output.push_str(value),
+            None => Err(format!("Unresolved variable '{token}'"))?,
+        }
+        rest = &rest[start + len + 2..];
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables() -> HashMap<String, String> {
+        HashMap::from([
+            ("host".to_string(), "api.example.com".to_string()),
+            ("id".to_string(), "42".to_string()),
+        ])
+    }
+
+    #[test]
+    fn substitutes_every_token() {
+        assert_eq!(
+            substitute("https://{{host}}/users/{{ id }}?again={{id}}", &variables()).unwrap(),
+            "https://api.example.com/users/42?again=42"
+        );
+    }
+
+    #[test]
+    fn leaves_text_without_tokens_alone() {
+        assert_eq!(
+            substitute("{\"a\": {\"b\": 1}}", &variables()).unwrap(),
+            "{\"a\": {\"b\": 1}}"
+        );
+        assert_eq!(
+            substitute("open {{ only", &variables()).unwrap(),
+            "open {{ only"
+        );
+    }
+
+    #[test]
+    fn unresolved_tokens_are_an_error() {
+        let err = substitute("/users/{{user_id}}", &variables()).unwrap_err();
+        assert_eq!(err.to_string(), "Unresolved variable '{{user_id}}'");
+    }
+}
@@ -0,0 +1,190 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+use tower_layer::Layer;
+use tower_service::Service;
+
+/// How long the phases of a request took.
+pub struct Timings {
+    /// Resolving the hostname, if it was looked up. There's no lookup when a pooled connection is
+    /// reused, the host is an IP address or it's resolved by an override
+    pub dns: Option<Duration>,
+    /// Setting up a new connection, or `None` if a pooled one was reused
+    pub connection: Option<ConnectionTimings>,
+    /// Whether the request was sent over TLS
+    pub encrypted: bool,
+    /// From sending the request until the response headers arrived
+    pub ttfb: Duration,
+    /// The whole exchange, including reading the body
+    pub total: Duration,
+}
+
+impl Timings {
+    /// The timings as returned by `LastTimings`.
+    pub fn to_json(&self) -> String {
+        serde_json::json!({
+            "dns_ms": self.dns.map(millis),
+            "connect_ms": self.connection.map(|c| millis(c.connect)),
+            "tls_ms": self.connection.and_then(|c| c.tls).map(millis),
+            "ttfb_ms": millis(self.ttfb),
+            "total_ms": millis(self.total),
+        })
+        .to_string()
+    }
+
+    /// The timings for evidence.
+    pub fn describe(&self) -> String {
+        let dns = self
+            .dns
+            .map_or("no lookup".to_string(), |dns| format!("{}ms", millis(dns)));
+        let (connect, tls) = match self.connection {
+            None => (
+                "reused a pooled connection".to_string(),
+                "reused a pooled connection".to_string(),
+            ),
+            Some(c) => (
+                format!("{}ms", millis(c.connect)),
+                match c.tls {
+                    Some(tls) => format!("{}ms", millis(tls)),
+                    None if self.encrypted => "counted in connecting".to_string(),
+                    None => "none".to_string(),
+                },
+            ),
+        };
+        format!(
+            "DNS: {dns}\r\nConnecting: {connect}\r\nTLS handshake: {tls}\r\nTime to first byte: {}ms\r\nTotal: {}ms",
+            millis(self.ttfb),
+            millis(self.total)
+        )
+    }
+}
+
+/// How long setting up a new connection took, after resolving its host.
+#[derive(Clone, Copy, Debug)]
+pub struct ConnectionTimings {
+    /// Opening the connection, including any proxy tunnel. The platform TLS library can't be
+    /// timed separately, so its handshakes are counted here too
+    pub connect: Duration,
+    /// The TLS handshake, if there was one and it was timed
+    pub tls: Option<Duration>,
+}
+
+/// Where the connector records how long the latest new connection took to set up. Only new
+/// connections reach the connector, so nothing is recorded when a pooled one is reused.
+#[derive(Clone, Debug, Default)]
+pub struct ConnectTimes(Arc<Mutex<ConnectState>>);
+
+#[derive(Debug, Default)]
+struct ConnectState {
+    /// When the connection being set up started
+    started: Option<Instant>,
+    /// When its TLS handshake started, if rustls reported it
+    tls_started: Option<Instant>,
+    /// The latest connection that was set up
+    latest: Option<ConnectionTimings>,
+}
+
+impl ConnectTimes {
+    /// Forget the latest connection, so a request can tell whether it opened a new one.
+    pub fn clear(&self) {
+        self.0.lock().latest = None;
+    }
+
+    /// Take the timings of the connection set up since [`Self::clear`], if there was one.
+    pub fn take(&self) -> Option<ConnectionTimings> {
+        self.0.lock().latest.take()
+    }
+
+    /// Record that the TLS handshake of the connection being set up started. Only the first
+    /// call for each connection counts.
+    pub fn tls_started(&self) {
+        let mut state = self.0.lock();
+        if state.started.is_some() && state.tls_started.is_none() {
+            state.tls_started = Some(Instant::now());
+        }
+    }
+
+    fn started(&self) {
+        let mut state = self.0.lock();
+        state.started = Some(Instant::now());
+        state.tls_started = None;
+    }
+
+    /// Record that the connection being set up is ready, `dns` of which was spent resolving.
+    fn finished(&self, dns: Option<Duration>) {
+        let finished = Instant::now();
+        let mut state = self.0.lock();
+        let Some(started) = state.started.take() else {
+            return;
+        };
+        let tls_started = state.tls_started.take();
+        let connected = tls_started.unwrap_or(finished);
+        state.latest = Some(ConnectionTimings {
+            connect: connected
+                .duration_since(started)
+                .saturating_sub(dns.unwrap_or_default()),
+            tls: tls_started.map(|tls_started| finished.duration_since(tls_started)),
+        });
+    }
+}
+
+/// A connector layer timing every new connection into [`ConnectTimes`].
+#[derive(Clone)]
+pub struct ConnectTimingLayer {
+    pub times: ConnectTimes,
+    /// Where the resolver records its latest lookup, which happens while connecting
+    pub dns_lookup_time: Arc<Mutex<Option<Duration>>>,
+}
+
+impl<S> Layer<S> for ConnectTimingLayer {
+    type Service = ConnectTiming<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ConnectTiming {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// The connector wrapped by [`ConnectTimingLayer`].
+#[derive(Clone)]
+pub struct ConnectTiming<S> {
+    inner: S,
+    layer: ConnectTimingLayer,
+}
+
+impl<S, R> Service<R> for ConnectTiming<S>
+where
+    S: Service<R>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: R) -> Self::Future {
+        let layer = self.layer.clone();
+        layer.times.started();
+        let connecting = self.inner.call(req);
+        Box::pin(async move {
+            let conn = connecting.await?;
+            layer.times.finished(*layer.dns_lookup_time.lock());
+            Ok(conn)
+        })
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
@@ -0,0 +1,379 @@
+use std::{collections::HashMap, net::IpAddr, sync::Arc};
+
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use parking_lot::Mutex;
+use reqwest::{blocking::Response, tls::TlsInfo};
+use rustls::{
+    CertificateError, DigitallySignedStruct, NamedGroup, RootCertStore, SignatureScheme,
+    client::{
+        ClientSessionMemoryCache, ClientSessionStore, Resumption, Tls12ClientSessionValue,
+        Tls13ClientSessionValue, WebPkiServerVerifier,
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    },
+    crypto::{self, CryptoProvider},
+    pki_types::{CertificateDer, ServerName, UnixTime},
+};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    client_config::{ClientConfig, HttpProtocol},
+    error::Result,
+    timings::ConnectTimes,
+};
+
+/// The base64-encoded SHA-256 hash of a DER certificate's SubjectPublicKeyInfo.
+pub fn spki_sha256(der: &[u8]) -> Result<String> {
+    let (_, cert) = x509_parser::parse_x509_certificate(der)
+        .map_err(|e| format!("Failed to parse the server certificate: {e}"))?;
+    Ok(STANDARD.encode(Sha256::digest(cert.public_key().raw)))
+}
+
+/// Checks server certificates during the handshake, so a request is never sent to a server
+/// whose leaf certificate doesn't match a pin for its host, and records the TLS version each
+/// host negotiated.
+#[derive(Debug)]
+pub struct CertificateVerifier {
+    /// Checks the chain and hostname, unless invalid certificates are accepted
+    webpki: Option<Arc<WebPkiServerVerifier>>,
+    accept_invalid_hostnames: bool,
+    provider: Arc<CryptoProvider>,
+    pins: HashMap<String, Vec<String>>,
+    /// Where the reason the latest handshake failed its pin check is recorded
+    failure: Arc<Mutex<Option<String>>>,
+    /// Where the TLS version negotiated with each host is recorded
+    negotiated: Arc<Mutex<HashMap<String, &'static str>>>,
+    /// The host whose handshake is in progress. Its certificate is checked before the handshake
+    /// signature, which is what shows the version
+    verifying: Mutex<Option<String>>,
+}
+
+impl CertificateVerifier {
+    /// Record the version of the handshake in progress, once its signature has been checked.
+    fn record(
+        &self,
+        version: &'static str,
+        result: std::result::Result<HandshakeSignatureValid, rustls::Error>,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        if result.is_ok()
+            && let Some(host) = self.verifying.lock().take()
+        {
+            self.negotiated.lock().insert(host, version);
+        }
+        result
+    }
+}
+
+impl ServerCertVerifier for CertificateVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        let host = match server_name {
+            ServerName::DnsName(name) => name.as_ref().to_lowercase(),
+            ServerName::IpAddress(ip) => IpAddr::from(*ip).to_string(),
+            _ => String::new(),
+        };
+        *self.verifying.lock() = Some(host.clone());
+        if let Some(webpki) = &self.webpki {
+            match webpki.verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                ocsp_response,
+                now,
+            ) {
+                Ok(_) => (),
+                Err(rustls::Error::InvalidCertificate(
+                    CertificateError::NotValidForName
+                    | CertificateError::NotValidForNameContext { .. },
+                )) if self.accept_invalid_hostnames => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        if let Some(pins) = self.pins.get(&host) {
+            let actual =
+                spki_sha256(end_entity).map_err(|e| rustls::Error::General(e.to_string()))?;
+            if !pins.contains(&actual) {
+                let message = format!(
+                    "Certificate pin mismatch for {host}: expected {}, got {actual}",
+                    pins.join(" or ")
+                );
+                *self.failure.lock() = Some(message.clone());
+                return Err(rustls::Error::General(message));
+            }
+        }
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.record(
+            "1.2",
+            crypto::verify_tls12_signature(
+                message,
+                cert,
+                dss,
+                &self.provider.signature_verification_algorithms,
+            ),
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.record(
+            "1.3",
+            crypto::verify_tls13_signature(
+                message,
+                cert,
+                dss,
+                &self.provider.signature_verification_algorithms,
+            ),
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// The rustls configuration used when [`ClientConfig::uses_rustls`], with the trusted roots, pins,
+/// TLS versions and ALPN protocols `config` asks for.
+pub fn rustls_config(config: &ClientConfig) -> Result<rustls::ClientConfig> {
+    let provider = Arc::new(crypto::ring::default_provider());
+    let webpki = if config.accept_invalid_certs {
+        None
+    } else {
+        let mut roots = RootCertStore::empty();
+        // Certificates the platform store can't provide are skipped, as the default TLS backend does
+        for cert in rustls_native_certs::load_native_certs().certs {
+            let _ = roots.add(cert);
+        }
+        for cert in &config.root_certificates {
+            roots
+                .add(cert.clone())
+                .map_err(|e| format!("Invalid root certificate: {e}"))?;
+        }
+        Some(
+            WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+                .build()
+                .map_err(|e| format!("Failed to set up certificate verification: {e}"))?,
+        )
+    };
+    let verifier = CertificateVerifier {
+        webpki,
+        accept_invalid_hostnames: config.accept_invalid_hostnames,
+        provider: provider.clone(),
+        pins: config.pins.clone(),
+        failure: config.pin_failure.clone(),
+        negotiated: config.negotiated_tls_versions.clone(),
+        verifying: Mutex::new(None),
+    };
+
+    let min = config.min_tls_version.as_deref().unwrap_or("1.0");
+    let max = config.max_tls_version.as_deref().unwrap_or("1.3");
+    let versions = [
+        ("1.2", &rustls::version::TLS12),
+        ("1.3", &rustls::version::TLS13),
+    ]
+    .into_iter()
+    .filter(|(version, _)| (min..=max).contains(version))
+    .map(|(_, version)| version)
+    .collect::<Vec<_>>();
+    if versions.is_empty() {
+        return Err(format!(
+            "Pinned certificates are checked with rustls, which only supports TLS 1.2 and 1.3, but TLS {min} to {max} was configured"
+        )
+        .into());
+    }
+
+    let mut tls = rustls::ClientConfig::builder_with_provider(provider)
+        .with_protocol_versions(&versions)
+        .map_err(|e| format!("Failed to set up TLS: {e}"))?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+    // A preconfigured TLS backend is used as-is, so the protocols to offer are set here
+    tls.alpn_protocols = match config.protocol {
+        HttpProtocol::Auto => vec![b"h2".to_vec(), b"http/1.1".to_vec()],
+        HttpProtocol::Http1Only => vec![b"http/1.1".to_vec()],
+        HttpProtocol::Http2PriorKnowledge => vec![b"h2".to_vec()],
+        #[cfg(feature = "http3")]
+        HttpProtocol::Http3 => vec![b"h3".to_vec()],
+    };
+    tls.resumption = Resumption::store(Arc::new(TimingSessionStore {
+        inner: ClientSessionMemoryCache::new(256),
+        times: config.connect_times.clone(),
+    }));
+    Ok(tls)
+}
+
+/// The default session cache, which also marks when each handshake starts, as rustls looks up
+/// the sessions it could resume before sending its hello.
+#[derive(Debug)]
+struct TimingSessionStore {
+    inner: ClientSessionMemoryCache,
+    times: ConnectTimes,
+}
+
+impl ClientSessionStore for TimingSessionStore {
+    fn set_kx_hint(&self, server_name: ServerName<'static>, group: NamedGroup) {
+        self.inner.set_kx_hint(server_name, group);
+    }
+
+    fn kx_hint(&self, server_name: &ServerName<'_>) -> Option<NamedGroup> {
+        self.times.tls_started();
+        self.inner.kx_hint(server_name)
+    }
+
+    fn set_tls12_session(&self, server_name: ServerName<'static>, value: Tls12ClientSessionValue) {
+        self.inner.set_tls12_session(server_name, value);
+    }
+
+    fn tls12_session(&self, server_name: &ServerName<'_>) -> Option<Tls12ClientSessionValue> {
+        self.times.tls_started();
+        self.inner.tls12_session(server_name)
+    }
+
+    fn remove_tls12_session(&self, server_name: &ServerName<'static>) {
+        self.inner.remove_tls12_session(server_name);
+    }
+
+    fn insert_tls13_ticket(
+        &self,
+        server_name: ServerName<'static>,
+        value: Tls13ClientSessionValue,
+    ) {
+        self.inner.insert_tls13_ticket(server_name, value);
+    }
+
+    fn take_tls13_ticket(
+        &self,
+        server_name: &ServerName<'static>,
+    ) -> Option<Tls13ClientSessionValue> {
+        self.times.tls_started();
+        self.inner.take_tls13_ticket(server_name)
+    }
+}
+
+/// Describe the TLS connection `res` arrived over for evidence, with the TLS version if it's
+/// known, or `None` for plain HTTP.
+pub fn describe(res: &Response, version: Option<&str>) -> Option<String> {
+    let der = res
+        .extensions()
+        .get::<TlsInfo>()
+        .and_then(TlsInfo::peer_certificate)?;
+    // The cipher suite isn't exposed by either TLS backend
+    let mut description = format!("HTTP version: {:?}\r\n", res.version());
+    if let Some(version) = version {
+        description.push_str(&format!("TLS version: {version}\r\n"));
+    }
+    match x509_parser::parse_x509_certificate(der) {
+        Ok((_, cert)) => {
+            description.push_str(&format!("Subject: {}\r\n", cert.subject()));
+            description.push_str(&format!("Issuer: {}\r\n", cert.issuer()));
+            description.push_str(&format!("Expires: {}\r\n", cert.validity().not_after));
+        }
+        Err(e) => description.push_str(&format!("Certificate: <unable to parse: {e}>\r\n")),
+    }
+    Some(description)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{self, TestServer};
+
+    /// A configuration that trusts the test CA and pins `localhost` to `pin`.
+    fn pinned(pin: &str) -> ClientConfig {
+        ClientConfig {
+            root_certificates: vec![test_server::ca_cert()],
+            pins: HashMap::from([("localhost".to_string(), vec![pin.to_string()])]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn matching_pins_connect() {
+        let server = TestServer::tls_responding("pinned");
+        let config = pinned(test_server::LOCALHOST_PIN);
+        let res = config.build().unwrap().get(server.https_url("/")).send();
+        assert_eq!(res.unwrap().text().unwrap(), "pinned");
+        assert!(config.pin_failure.lock().is_none());
+        assert_eq!(
+            config.negotiated_tls_versions.lock().get("localhost"),
+            Some(&"1.3")
+        );
+    }
+
+    #[test]
+    fn mismatched_pins_abort_the_handshake() {
+        let server = TestServer::tls_responding("pinned");
+        let other = STANDARD.encode([0; 32]);
+        let config = pinned(&other);
+        let res = config.build().unwrap().get(server.https_url("/")).send();
+        assert!(res.is_err());
+        assert_eq!(
+            config.pin_failure.lock().as_deref(),
+            Some(
+                format!(
+                    "Certificate pin mismatch for localhost: expected {other}, got {}",
+                    test_server::LOCALHOST_PIN
+                )
+                .as_str()
+            )
+        );
+    }
+
+    #[test]
+    fn pins_for_other_hosts_are_ignored() {
+        let server = TestServer::tls_responding("unpinned");
+        let config = ClientConfig {
+            root_certificates: vec![test_server::ca_cert()],
+            pins: HashMap::from([("example.com".to_string(), vec![STANDARD.encode([0; 32])])]),
+            ..Default::default()
+        };
+        let res = config.build().unwrap().get(server.https_url("/")).send();
+        assert_eq!(res.unwrap().text().unwrap(), "unpinned");
+    }
+
+    #[test]
+    fn rustls_offers_the_configured_protocols() {
+        let config = ClientConfig {
+            protocol: HttpProtocol::Http1Only,
+            accept_invalid_certs: true,
+            ..Default::default()
+        };
+        let tls = rustls_config(&config).unwrap();
+        assert_eq!(tls.alpn_protocols, vec![b"http/1.1".to_vec()]);
+    }
+
+    #[test]
+    fn pinned_connections_reject_versions_before_tls_1_2() {
+        let config = ClientConfig {
+            max_tls_version: Some("1.1".to_string()),
+            accept_invalid_certs: true,
+            pins: HashMap::from([("example.com".to_string(), vec![])]),
+            ..Default::default()
+        };
+        let err = rustls_config(&config).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Pinned certificates are checked with rustls, which only supports TLS 1.2 and 1.3, but TLS 1.0 to 1.1 was configured"
+        );
+    }
+}